@@ -24,7 +24,7 @@ fn main() {
         types_decl.import("crate::errors", "SignaldError");
 
         let mut variants: Vec<Variant> = Vec::new();
-        for version in vec!["v1", "v0"].iter() {
+        for version in ["v1", "v0"].iter() {
             let types = types[*version].as_object().unwrap();
             add_types(&mut types_decl, types, version, &mut variants);
         }
@@ -58,13 +58,16 @@ fn main() {
         actions_decl.import("uuid", "Uuid");
         actions_decl.import("crate::socket", "MessageCommon");
         actions_decl.import("crate::errors", "SignaldError");
+        actions_decl.import("crate::interceptor", "Interceptor");
+        actions_decl.import("std::sync", "Arc");
 
-        add_actions(&mut actions_decl, actions, "v1");
+        add_actions(&mut actions_decl, actions, "v1", false);
 
         actions_decl.new_struct("SocketWrapper")
             .vis("pub")
             .generic("T")
-            .field("pub socket", "T");
+            .field("pub socket", "T")
+            .field("pub(crate) interceptors", "Vec<Arc<dyn Interceptor>>");
 
 
         let mut source_file = File::create("src/actions.rs")
@@ -72,15 +75,36 @@ fn main() {
 
         source_file.write_all(actions_decl.to_string().as_bytes())
             .expect("Failed to write to source file");
+
+        let mut blocking_decl = Scope::new();
+        blocking_decl.import("crate::blocking", "Client");
+        blocking_decl.import("crate::blocking", "SocketError");
+        blocking_decl.import("crate::types", "*");
+        blocking_decl.import("uuid", "Uuid");
+        blocking_decl.import("crate::socket", "MessageCommon");
+        blocking_decl.import("crate::errors", "SignaldError");
+
+        add_actions(&mut blocking_decl, actions, "v1", true);
+
+        let mut source_file = File::create("src/blocking_actions.rs")
+            .expect("Can't create source file");
+
+        source_file.write_all(blocking_decl.to_string().as_bytes())
+            .expect("Failed to write to source file");
     }
 }
 
-fn add_actions(scope: &mut Scope, actions: &Map<String, Value>, version: &str) {
+fn add_actions(scope: &mut Scope, actions: &Map<String, Value>, version: &str, blocking: bool) {
     let version = version.to_uppercase();
-    let api_impl = scope.new_impl("SocketWrapper")
-        .generic("T")
-        .target_generic("T")
-        .bound("T", "AsyncSocket");
+    let api_impl = if blocking {
+        scope.new_impl("Client")
+    } else {
+        scope.new_impl("SocketWrapper")
+            .generic("T")
+            .target_generic("T")
+            .bound("T", "AsyncSocket + Send")
+    };
+    let await_ = if blocking { "" } else { ".await" };
 
     let mut lines = Vec::new();
 
@@ -98,10 +122,10 @@ fn add_actions(scope: &mut Scope, actions: &Map<String, Value>, version: &str) {
 
         lines.push(format!("    \"{}\" => {{", key));
         lines.push(format!("        if let SignaldTypes::{}(msg) = msg {{", request_type));
-        lines.push(format!("            self.{}(msg, Some(id)).await", key));
+        lines.push(format!("            self.{}(msg, Some(id)){}", key, await_));
 
         if let Some(response) = &response_type {
-            lines.push(format!("                .map(|response| SignaldTypes::{}(response))", response));
+            lines.push(format!("                .map(SignaldTypes::{})", response));
         } else {
             lines.push("                .map(|_| SignaldTypes::NoResponse)".to_owned())
         }
@@ -120,7 +144,7 @@ fn add_actions(scope: &mut Scope, actions: &Map<String, Value>, version: &str) {
 
         new_fn
             .vis("pub")
-            .set_async(true)
+            .set_async(!blocking)
             .ret(format!(
                     "Result<{}, SocketError>",
                     match &response_type {
@@ -141,12 +165,19 @@ fn add_actions(scope: &mut Scope, actions: &Map<String, Value>, version: &str) {
             .line("    \"v1\".to_owned(),")
             .line("    msg")
             .line(");")
-            .line("")
-            .line("let mut msg = serde_json::to_vec(&msg).unwrap();")
-            .line("msg.push(b'\\n');")
-            .line("")
-            .line("self.socket.write(&msg, &id).await?;")
-            .line("let response = self.socket.get_response(id).await?;")
+            .line("");
+
+        if blocking {
+            new_fn
+                .line("let mut msg = serde_json::to_vec(&msg).unwrap();")
+                .line("msg.push(b'\\n');")
+                .line("")
+                .line(format!("let response = crate::trace::request_blocking(&id, \"{}\", || self.call(&msg, &id))?;", key));
+        } else {
+            new_fn.line(format!("let response = crate::trace::request(&id, \"{}\", self.call(&msg, &id)).await?;", key));
+        }
+
+        new_fn
             .line("")
             .line("match response.get(\"error\") {")
             .line(
@@ -162,7 +193,7 @@ fn add_actions(scope: &mut Scope, actions: &Map<String, Value>, version: &str) {
     }
 
     let call_fn = api_impl.new_fn("remote_call")
-        .set_async(true)
+        .set_async(!blocking)
         .vis("pub")
         .arg_mut_self()
         .arg("api_fn", "&str")
@@ -220,7 +251,7 @@ fn add_types(scope: &mut Scope, types: &Map<String, Value>, version: &str, varia
 
 }
 
-fn get_field(field: &String, info: &Value) -> Field {
+fn get_field(field: &str, info: &Value) -> Field {
     let mut doc = Vec::new();
     let mut example = String::from("Example: ");
 
@@ -255,7 +286,7 @@ fn get_field(field: &String, info: &Value) -> Field {
     };
 
     let mut new_field = get_clean_field(
-        field.as_str().clone(),
+        field,
         &new_field
     );
 
@@ -0,0 +1,159 @@
+//! Derive macros for the `signald` crate. Use them through `signald`'s `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Error, Expr, Fields, Lit, LitStr, Meta, Type};
+
+/// Derive `signald::command::BotCommand` for an enum, with a command for each variant.
+///
+/// Variants are named after their lowercased name, or `#[command(name = "...")]`, and described
+/// by their doc comment. Their fields are the command's arguments, in order.
+#[proc_macro_derive(BotCommand, attributes(command))]
+pub fn derive_bot_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    bot_command(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+struct Command {
+    name: String,
+    description: Option<String>,
+    usage: String,
+    parse: TokenStream2
+}
+
+fn bot_command(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => { return Err(Error::new_spanned(&input.ident, "BotCommand can only be derived for enums")); }
+    };
+
+    let ident = &input.ident;
+    let commands = data.variants.iter()
+        .map(|variant| {
+            let name = command_name(&variant.attrs)?
+                .unwrap_or_else(|| variant.ident.to_string().to_lowercase());
+            let variant_ident = &variant.ident;
+
+            let arguments: Vec<_> = match &variant.fields {
+                Fields::Named(fields) => fields.named.iter()
+                    .map(|field| (field.ident.clone().unwrap(), field.ident.as_ref().unwrap().to_string(), &field.ty))
+                    .collect(),
+                Fields::Unnamed(fields) => fields.unnamed.iter()
+                    .enumerate()
+                    .map(|(i, field)| (format_ident!("arg{}", i), format!("arg{}", i + 1), &field.ty))
+                    .collect(),
+                Fields::Unit => Vec::new()
+            };
+
+            let mut usage = format!("/{}", name);
+            for (_, arg_name, ty) in &arguments {
+                if is_option(ty) {
+                    usage.push_str(&format!(" [{}]", arg_name));
+                } else {
+                    usage.push_str(&format!(" <{}>", arg_name));
+                }
+            }
+
+            let idents: Vec<_> = arguments.iter().map(|(ident, _, _)| ident).collect();
+            let arg_names: Vec<_> = arguments.iter().map(|(_, arg_name, _)| arg_name).collect();
+            let construct = match &variant.fields {
+                Fields::Named(_) => quote!(#ident::#variant_ident { #(#idents),* }),
+                Fields::Unnamed(_) => quote!(#ident::#variant_ident(#(#idents),*)),
+                Fields::Unit => quote!(#ident::#variant_ident)
+            };
+
+            let parse = quote! {
+                let mut args = ::signald::command::Args::new(args, #usage);
+                #(let #idents = args.next(#arg_names)?;)*
+                args.finish()?;
+                Ok(#construct)
+            };
+
+            Ok(Command {
+                name,
+                description: description(&variant.attrs),
+                usage,
+                parse
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let names: Vec<_> = commands.iter().map(|command| &command.name).collect();
+    let parses: Vec<_> = commands.iter().map(|command| &command.parse).collect();
+    let help = commands.iter()
+        .map(|command| match &command.description {
+            Some(description) => format!("{} - {}", command.usage, description),
+            None => command.usage.clone()
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::signald::command::BotCommand for #ident #ty_generics #where_clause {
+            fn names() -> &'static [&'static str] {
+                &[#(#names),*]
+            }
+
+            fn parse_args(name: &str, args: &[String]) -> Result<Self, ::signald::command::UsageError> {
+                match name {
+                    #(#names => { #parses })*
+                    _ => Err(::signald::command::UsageError::new(format!("Unknown command /{}", name)))
+                }
+            }
+
+            fn help() -> String {
+                #help.to_owned()
+            }
+        }
+    })
+}
+
+/// The name set with `#[command(name = "...")]`
+fn command_name(attrs: &[Attribute]) -> Result<Option<String>, Error> {
+    let mut name = None;
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("command")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("unsupported command attribute"))
+            }
+        })?;
+    }
+
+    Ok(name)
+}
+
+/// The doc comment, joined into a single line
+fn description(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<_> = attrs.iter()
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(meta) if meta.path.is_ident("doc") => match &meta.value {
+                Expr::Lit(expr) => match &expr.lit {
+                    Lit::Str(doc) => Some(doc.value().trim().to_owned()),
+                    _ => None
+                },
+                _ => None
+            },
+            _ => None
+        })
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join(" "))
+    }
+}
+
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path.path.segments.last().is_some_and(|segment| segment.ident == "Option"),
+        _ => false
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::Duration;
+use signald::command::{self, BotCommand};
+use signald::types::JsonDataMessageV1;
+
+#[derive(BotCommand, Debug, PartialEq)]
+enum Command {
+    /// Remind you about something later
+    Remind { after: Duration, text: String },
+    /// Roll a die, with 6 sides unless given
+    #[command(name = "dice")]
+    Roll(Option<u32>),
+    Ping
+}
+
+#[test]
+fn parse() {
+    assert_eq!(
+        command::parse(r#"/remind 10m "stand up""#),
+        Some(Ok(Command::Remind { after: Duration::from_secs(600), text: "stand up".to_owned() }))
+    );
+    assert_eq!(command::parse("/dice"), Some(Ok(Command::Roll(None))));
+    assert_eq!(command::parse("/dice 20"), Some(Ok(Command::Roll(Some(20)))));
+    assert_eq!(command::parse("  /ping  "), Some(Ok(Command::Ping)));
+
+    // Not one of our commands
+    assert_eq!(command::parse::<Command>("/roll"), None);
+    assert_eq!(command::parse::<Command>("ping"), None);
+
+    let message = JsonDataMessageV1 {
+        body: Some("/ping".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(message.command(), Some(Ok(Command::Ping)));
+}
+
+#[test]
+fn usage_errors() {
+    let error = command::parse::<Command>("/remind 10m").unwrap().unwrap_err();
+    assert_eq!(error.to_string(), "Missing text\nUsage: /remind <after> <text>");
+
+    let error = command::parse::<Command>("/remind soon stuff").unwrap().unwrap_err();
+    assert_eq!(error.message, "Invalid after: expected a duration like 10m or 1h30m, not soon");
+
+    let error = command::parse::<Command>("/ping pong").unwrap().unwrap_err();
+    assert_eq!(error.to_string(), "Unexpected argument pong\nUsage: /ping");
+}
+
+#[test]
+fn help() {
+    assert_eq!(Command::help(), "/remind <after> <text> - Remind you about something later\n\
+                                 /dice [arg1] - Roll a die, with 6 sides unless given\n\
+                                 /ping");
+}
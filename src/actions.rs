@@ -3,9 +3,11 @@ use crate::SocketError;
 use crate::types::*;
 use uuid::Uuid;
 use crate::errors::SignaldError;
+use crate::interceptor::Interceptor;
+use std::sync::Arc;
 
 impl<T> SocketWrapper<T>
-where T: AsyncSocket,
+where T: AsyncSocket + Send,
 {
     /// Accept a v2 group invitation. Note that you must have a profile name set to join groups.
     pub async fn accept_invitation(&mut self, msg: AcceptInvitationRequestV1, id: Option<Uuid>) -> Result<JsonGroupV2InfoV1, SocketError> {
@@ -20,11 +22,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "accept_invitation", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<JsonGroupV2InfoV1>(response).unwrap()),
@@ -45,11 +43,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "add_device", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -70,11 +64,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "add_server", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<String>(response).unwrap()),
@@ -95,11 +85,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "approve_membership", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<JsonGroupV2InfoV1>(response).unwrap()),
@@ -119,11 +105,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "create_group", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<JsonGroupV2InfoV1>(response).unwrap()),
@@ -144,11 +126,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "delete_account", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -168,11 +146,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "delete_server", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -193,11 +167,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "finish_link", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<AccountV1>(response).unwrap()),
@@ -218,11 +188,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "generate_linking_uri", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<LinkingURIV1>(response).unwrap()),
@@ -243,11 +209,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "get_all_identities", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<AllIdentityKeyListV1>(response).unwrap()),
@@ -268,11 +230,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "get_group", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<JsonGroupV2InfoV1>(response).unwrap()),
@@ -293,11 +251,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "get_identities", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<IdentityKeyListV1>(response).unwrap()),
@@ -318,11 +272,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "get_linked_devices", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<LinkedDevicesV1>(response).unwrap()),
@@ -343,11 +293,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "get_profile", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<ProfileV1>(response).unwrap()),
@@ -368,11 +314,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "get_remote_config", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<RemoteConfigListV1>(response).unwrap()),
@@ -392,11 +334,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "get_servers", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<ServerListV1>(response).unwrap()),
@@ -417,11 +355,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "group_link_info", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<JsonGroupJoinInfoV1>(response).unwrap()),
@@ -442,11 +376,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "join_group", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<JsonGroupJoinInfoV1>(response).unwrap()),
@@ -466,11 +396,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "leave_group", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<GroupInfoV1>(response).unwrap()),
@@ -491,11 +417,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "list_accounts", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<AccountListV1>(response).unwrap()),
@@ -515,11 +437,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "list_contacts", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<ProfileListV1>(response).unwrap()),
@@ -539,11 +457,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "list_groups", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<GroupListV1>(response).unwrap()),
@@ -563,11 +477,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "mark_read", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -588,11 +498,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "react", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<SendResponseV1>(response).unwrap()),
@@ -613,11 +519,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "refuse_membership", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<JsonGroupV2InfoV1>(response).unwrap()),
@@ -638,11 +540,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "register", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<AccountV1>(response).unwrap()),
@@ -663,11 +561,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "remote_delete", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<SendResponseV1>(response).unwrap()),
@@ -688,11 +582,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "remove_linked_device", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -713,11 +603,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "request_sync", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -738,11 +624,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "reset_session", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<SendResponseV1>(response).unwrap()),
@@ -763,11 +645,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "resolve_address", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<JsonAddressV1>(response).unwrap()),
@@ -787,11 +665,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "send", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<SendResponseV1>(response).unwrap()),
@@ -812,11 +686,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "send_payment", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<SendResponseV1>(response).unwrap()),
@@ -837,11 +707,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "set_device_name", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -862,11 +728,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "set_expiration", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<SendResponseV1>(response).unwrap()),
@@ -886,11 +748,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "set_profile", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -911,11 +769,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "subscribe", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -936,11 +790,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "trust", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -961,11 +811,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "typing", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -986,11 +832,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "unsubscribe", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(()),
@@ -1011,11 +853,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "update_contact", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<ProfileV1>(response).unwrap()),
@@ -1036,11 +874,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "update_group", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<GroupInfoV1>(response).unwrap()),
@@ -1061,11 +895,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "verify", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<AccountV1>(response).unwrap()),
@@ -1085,11 +915,7 @@ where T: AsyncSocket,
             msg
         );
 
-        let mut msg = serde_json::to_vec(&msg).unwrap();
-        msg.push(b'\n');
-
-        self.socket.write(&msg, &id).await?;
-        let response = self.socket.get_response(id).await?;
+        let response = crate::trace::request(&id, "version", self.call(&msg, &id)).await?;
 
         match response.get("error") {
             None => Ok(serde_json::from_value::<JsonVersionMessageV1>(response).unwrap()),
@@ -1103,7 +929,7 @@ where T: AsyncSocket,
             "accept_invitation" => {
                 if let SignaldTypes::AcceptInvitationRequestV1(msg) = msg {
                     self.accept_invitation(msg, Some(id)).await
-                        .map(|response| SignaldTypes::JsonGroupV2InfoV1(response))
+                        .map(SignaldTypes::JsonGroupV2InfoV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1119,7 +945,7 @@ where T: AsyncSocket,
             "add_server" => {
                 if let SignaldTypes::AddServerRequestV1(msg) = msg {
                     self.add_server(msg, Some(id)).await
-                        .map(|response| SignaldTypes::String(response))
+                        .map(SignaldTypes::String)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1127,7 +953,7 @@ where T: AsyncSocket,
             "approve_membership" => {
                 if let SignaldTypes::ApproveMembershipRequestV1(msg) = msg {
                     self.approve_membership(msg, Some(id)).await
-                        .map(|response| SignaldTypes::JsonGroupV2InfoV1(response))
+                        .map(SignaldTypes::JsonGroupV2InfoV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1135,7 +961,7 @@ where T: AsyncSocket,
             "create_group" => {
                 if let SignaldTypes::CreateGroupRequestV1(msg) = msg {
                     self.create_group(msg, Some(id)).await
-                        .map(|response| SignaldTypes::JsonGroupV2InfoV1(response))
+                        .map(SignaldTypes::JsonGroupV2InfoV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1159,7 +985,7 @@ where T: AsyncSocket,
             "finish_link" => {
                 if let SignaldTypes::FinishLinkRequestV1(msg) = msg {
                     self.finish_link(msg, Some(id)).await
-                        .map(|response| SignaldTypes::AccountV1(response))
+                        .map(SignaldTypes::AccountV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1167,7 +993,7 @@ where T: AsyncSocket,
             "generate_linking_uri" => {
                 if let SignaldTypes::GenerateLinkingURIRequestV1(msg) = msg {
                     self.generate_linking_uri(msg, Some(id)).await
-                        .map(|response| SignaldTypes::LinkingURIV1(response))
+                        .map(SignaldTypes::LinkingURIV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1175,7 +1001,7 @@ where T: AsyncSocket,
             "get_all_identities" => {
                 if let SignaldTypes::GetAllIdentitiesV1(msg) = msg {
                     self.get_all_identities(msg, Some(id)).await
-                        .map(|response| SignaldTypes::AllIdentityKeyListV1(response))
+                        .map(SignaldTypes::AllIdentityKeyListV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1183,7 +1009,7 @@ where T: AsyncSocket,
             "get_group" => {
                 if let SignaldTypes::GetGroupRequestV1(msg) = msg {
                     self.get_group(msg, Some(id)).await
-                        .map(|response| SignaldTypes::JsonGroupV2InfoV1(response))
+                        .map(SignaldTypes::JsonGroupV2InfoV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1191,7 +1017,7 @@ where T: AsyncSocket,
             "get_identities" => {
                 if let SignaldTypes::GetIdentitiesRequestV1(msg) = msg {
                     self.get_identities(msg, Some(id)).await
-                        .map(|response| SignaldTypes::IdentityKeyListV1(response))
+                        .map(SignaldTypes::IdentityKeyListV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1199,7 +1025,7 @@ where T: AsyncSocket,
             "get_linked_devices" => {
                 if let SignaldTypes::GetLinkedDevicesRequestV1(msg) = msg {
                     self.get_linked_devices(msg, Some(id)).await
-                        .map(|response| SignaldTypes::LinkedDevicesV1(response))
+                        .map(SignaldTypes::LinkedDevicesV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1207,7 +1033,7 @@ where T: AsyncSocket,
             "get_profile" => {
                 if let SignaldTypes::GetProfileRequestV1(msg) = msg {
                     self.get_profile(msg, Some(id)).await
-                        .map(|response| SignaldTypes::ProfileV1(response))
+                        .map(SignaldTypes::ProfileV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1215,7 +1041,7 @@ where T: AsyncSocket,
             "get_remote_config" => {
                 if let SignaldTypes::RemoteConfigRequestV1(msg) = msg {
                     self.get_remote_config(msg, Some(id)).await
-                        .map(|response| SignaldTypes::RemoteConfigListV1(response))
+                        .map(SignaldTypes::RemoteConfigListV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1223,7 +1049,7 @@ where T: AsyncSocket,
             "get_servers" => {
                 if let SignaldTypes::GetServersRequestV1(msg) = msg {
                     self.get_servers(msg, Some(id)).await
-                        .map(|response| SignaldTypes::ServerListV1(response))
+                        .map(SignaldTypes::ServerListV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1231,7 +1057,7 @@ where T: AsyncSocket,
             "group_link_info" => {
                 if let SignaldTypes::GroupLinkInfoRequestV1(msg) = msg {
                     self.group_link_info(msg, Some(id)).await
-                        .map(|response| SignaldTypes::JsonGroupJoinInfoV1(response))
+                        .map(SignaldTypes::JsonGroupJoinInfoV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1239,7 +1065,7 @@ where T: AsyncSocket,
             "join_group" => {
                 if let SignaldTypes::JoinGroupRequestV1(msg) = msg {
                     self.join_group(msg, Some(id)).await
-                        .map(|response| SignaldTypes::JsonGroupJoinInfoV1(response))
+                        .map(SignaldTypes::JsonGroupJoinInfoV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1247,7 +1073,7 @@ where T: AsyncSocket,
             "leave_group" => {
                 if let SignaldTypes::LeaveGroupRequestV1(msg) = msg {
                     self.leave_group(msg, Some(id)).await
-                        .map(|response| SignaldTypes::GroupInfoV1(response))
+                        .map(SignaldTypes::GroupInfoV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1255,7 +1081,7 @@ where T: AsyncSocket,
             "list_accounts" => {
                 if let SignaldTypes::ListAccountsRequestV1(msg) = msg {
                     self.list_accounts(msg, Some(id)).await
-                        .map(|response| SignaldTypes::AccountListV1(response))
+                        .map(SignaldTypes::AccountListV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1263,7 +1089,7 @@ where T: AsyncSocket,
             "list_contacts" => {
                 if let SignaldTypes::ListContactsRequestV1(msg) = msg {
                     self.list_contacts(msg, Some(id)).await
-                        .map(|response| SignaldTypes::ProfileListV1(response))
+                        .map(SignaldTypes::ProfileListV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1271,7 +1097,7 @@ where T: AsyncSocket,
             "list_groups" => {
                 if let SignaldTypes::ListGroupsRequestV1(msg) = msg {
                     self.list_groups(msg, Some(id)).await
-                        .map(|response| SignaldTypes::GroupListV1(response))
+                        .map(SignaldTypes::GroupListV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1287,7 +1113,7 @@ where T: AsyncSocket,
             "react" => {
                 if let SignaldTypes::ReactRequestV1(msg) = msg {
                     self.react(msg, Some(id)).await
-                        .map(|response| SignaldTypes::SendResponseV1(response))
+                        .map(SignaldTypes::SendResponseV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1295,7 +1121,7 @@ where T: AsyncSocket,
             "refuse_membership" => {
                 if let SignaldTypes::RefuseMembershipRequestV1(msg) = msg {
                     self.refuse_membership(msg, Some(id)).await
-                        .map(|response| SignaldTypes::JsonGroupV2InfoV1(response))
+                        .map(SignaldTypes::JsonGroupV2InfoV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1303,7 +1129,7 @@ where T: AsyncSocket,
             "register" => {
                 if let SignaldTypes::RegisterRequestV1(msg) = msg {
                     self.register(msg, Some(id)).await
-                        .map(|response| SignaldTypes::AccountV1(response))
+                        .map(SignaldTypes::AccountV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1311,7 +1137,7 @@ where T: AsyncSocket,
             "remote_delete" => {
                 if let SignaldTypes::RemoteDeleteRequestV1(msg) = msg {
                     self.remote_delete(msg, Some(id)).await
-                        .map(|response| SignaldTypes::SendResponseV1(response))
+                        .map(SignaldTypes::SendResponseV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1335,7 +1161,7 @@ where T: AsyncSocket,
             "reset_session" => {
                 if let SignaldTypes::ResetSessionRequestV1(msg) = msg {
                     self.reset_session(msg, Some(id)).await
-                        .map(|response| SignaldTypes::SendResponseV1(response))
+                        .map(SignaldTypes::SendResponseV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1343,7 +1169,7 @@ where T: AsyncSocket,
             "resolve_address" => {
                 if let SignaldTypes::ResolveAddressRequestV1(msg) = msg {
                     self.resolve_address(msg, Some(id)).await
-                        .map(|response| SignaldTypes::JsonAddressV1(response))
+                        .map(SignaldTypes::JsonAddressV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1351,7 +1177,7 @@ where T: AsyncSocket,
             "send" => {
                 if let SignaldTypes::SendRequestV1(msg) = msg {
                     self.send(msg, Some(id)).await
-                        .map(|response| SignaldTypes::SendResponseV1(response))
+                        .map(SignaldTypes::SendResponseV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1359,7 +1185,7 @@ where T: AsyncSocket,
             "send_payment" => {
                 if let SignaldTypes::SendPaymentRequestV1(msg) = msg {
                     self.send_payment(msg, Some(id)).await
-                        .map(|response| SignaldTypes::SendResponseV1(response))
+                        .map(SignaldTypes::SendResponseV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1375,7 +1201,7 @@ where T: AsyncSocket,
             "set_expiration" => {
                 if let SignaldTypes::SetExpirationRequestV1(msg) = msg {
                     self.set_expiration(msg, Some(id)).await
-                        .map(|response| SignaldTypes::SendResponseV1(response))
+                        .map(SignaldTypes::SendResponseV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1423,7 +1249,7 @@ where T: AsyncSocket,
             "update_contact" => {
                 if let SignaldTypes::UpdateContactRequestV1(msg) = msg {
                     self.update_contact(msg, Some(id)).await
-                        .map(|response| SignaldTypes::ProfileV1(response))
+                        .map(SignaldTypes::ProfileV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1431,7 +1257,7 @@ where T: AsyncSocket,
             "update_group" => {
                 if let SignaldTypes::UpdateGroupRequestV1(msg) = msg {
                     self.update_group(msg, Some(id)).await
-                        .map(|response| SignaldTypes::GroupInfoV1(response))
+                        .map(SignaldTypes::GroupInfoV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1439,7 +1265,7 @@ where T: AsyncSocket,
             "verify" => {
                 if let SignaldTypes::VerifyRequestV1(msg) = msg {
                     self.verify(msg, Some(id)).await
-                        .map(|response| SignaldTypes::AccountV1(response))
+                        .map(SignaldTypes::AccountV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1447,7 +1273,7 @@ where T: AsyncSocket,
             "version" => {
                 if let SignaldTypes::VersionRequestV1(msg) = msg {
                     self.version(msg, Some(id)).await
-                        .map(|response| SignaldTypes::JsonVersionMessageV1(response))
+                        .map(SignaldTypes::JsonVersionMessageV1)
                 } else {
                     Err(SocketError::General("Incorrect message type"))
                 }
@@ -1459,4 +1285,5 @@ where T: AsyncSocket,
 
 pub struct SocketWrapper<T> {
     pub socket: T,
+    pub(crate) interceptors: Vec<Arc<dyn Interceptor>>,
 }
\ No newline at end of file
@@ -0,0 +1,84 @@
+//! Normalizing and comparing addresses.
+//!
+//! The same contact can show up with a formatted or bare number, an upper or lower case UUID, or
+//! only one of the two. Addresses are compared by UUID when both have one, and by number
+//! otherwise, after normalizing both.
+
+use uuid::Uuid;
+
+use crate::types::JsonAddressV1;
+
+/// A number in e164 form, ignoring spaces, dashes, dots and parentheses. Returns `None` if it
+/// has no digits or any other characters.
+pub fn normalize_number(number: &str) -> Option<String> {
+    let number = number.trim();
+    let number = number.strip_prefix('+').unwrap_or(number);
+
+    if !number.chars().all(|c| c.is_ascii_digit() || " -.()".contains(c)) {
+        return None;
+    }
+
+    let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+    match digits.is_empty() {
+        true => None,
+        false => Some(format!("+{}", digits))
+    }
+}
+
+/// A UUID in its lowercase hyphenated form
+pub fn normalize_uuid(uuid: &str) -> Option<String> {
+    Uuid::parse_str(uuid.trim()).ok().map(|uuid| uuid.to_hyphenated().to_string())
+}
+
+/// The normalized number and UUID of `address`
+pub(crate) fn normalize(address: &JsonAddressV1) -> (Option<String>, Option<String>) {
+    (
+        address.number.as_deref().and_then(normalize_number),
+        address.uuid.as_deref().and_then(normalize_uuid)
+    )
+}
+
+/// The address for a number or UUID, as written in config or an account name
+#[cfg(any(feature = "bot", feature = "rules"))]
+pub(crate) fn address_of(identifier: &str) -> JsonAddressV1 {
+    match normalize_uuid(identifier) {
+        Some(_) => JsonAddressV1 { uuid: Some(identifier.to_owned()), ..Default::default() },
+        None => JsonAddressV1 { number: Some(identifier.to_owned()), ..Default::default() }
+    }
+}
+
+/// Whether two addresses are the same contact
+pub fn same_address(a: &JsonAddressV1, b: &JsonAddressV1) -> bool {
+    let (a_number, a_uuid) = normalize(a);
+    let (b_number, b_uuid) = normalize(b);
+
+    match (a_uuid, b_uuid) {
+        (Some(a), Some(b)) => a == b,
+        _ => a_number.is_some() && a_number == b_number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::same_address;
+    use crate::types::JsonAddressV1;
+
+    fn address(number: Option<&str>, uuid: Option<&str>) -> JsonAddressV1 {
+        JsonAddressV1 {
+            number: number.map(str::to_owned),
+            uuid: uuid.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compares_normalized_addresses() {
+        let uuid = "9c2a1f8e-4b3d-4e5f-8a6b-7c8d9e0f1a2b";
+        assert!(same_address(&address(Some("+1 (555) 000-0001"), None), &address(Some("15550000001"), None)));
+        assert!(same_address(&address(None, Some(uuid)), &address(Some("+15550000002"), Some(&uuid.to_uppercase()))));
+        assert!(same_address(&address(Some("+15550000001"), None), &address(Some("+15550000001"), Some(uuid))));
+        assert!(!same_address(&address(Some("+15550000001"), Some(uuid)), &address(Some("+15550000001"), Some("00000000-0000-0000-0000-000000000000"))));
+        assert!(!same_address(&address(None, Some(uuid)), &address(Some("+15550000001"), None)));
+        assert!(!same_address(&address(Some("not a number"), None), &address(Some("not a number"), None)));
+    }
+}
@@ -4,7 +4,8 @@ use async_std::path::Path;
 use async_std::io::Error;
 use async_std::io::BufReader;
 use async_std::channel::{bounded, Receiver, RecvError, Sender};
-use async_std::sync::{Arc, Mutex};
+use async_std::sync::Arc;
+use async_std::task::JoinHandle;
 use std::collections::HashMap;
 use std::fmt::{Debug, Display};
 use async_trait::async_trait;
@@ -12,7 +13,7 @@ use serde_json::Value;
 use uuid::Uuid;
 
 use crate::errors::SignaldError;
-use crate::socket::AsyncSocket;
+use crate::socket::{AsyncSocket, PendingRequest};
 use crate::actions::SocketWrapper;
 use crate::types::IncomingMessageV1;
 
@@ -57,36 +58,42 @@ impl From<RecvError> for SocketError {
     }
 }
 
-pub type Map = Arc<Mutex<HashMap<Uuid, (Sender<Value>, Receiver<Value>)>>>;
+pub type Map = Arc<std::sync::Mutex<HashMap<Uuid, (Sender<Value>, Receiver<Value>)>>>;
 
 pub struct Socket<T> {
     socket: T,
-    response_map: Map,
-    listening: Arc<Mutex<bool>>
+    pub(crate) response_map: Map,
+    /// Holds its own handle to the stream, so it is cancelled on drop to close the connection
+    listener: Option<JoinHandle<()>>
 }
 
 #[async_trait]
 impl AsyncSocket for Socket<UnixStream> {
     async fn write<'a>(&'a mut self, buf: &'a [u8], id: &Uuid) -> Result<(), SocketError> {
         let channel = bounded(1);
-        self.response_map.lock().await.insert(
+        self.response_map.lock().unwrap().insert(
             *id,
             channel
         );
+        let pending = PendingRequest::new(&self.response_map, *id);
 
         match self.socket.write_all(buf).await {
-            Ok(()) => Ok(()),
+            Ok(()) => {
+                pending.keep();
+                Ok(())
+            },
             Err(e) => Err(SocketError::Io(e))
         }
     }
 
     async fn get_response<'a>(&'a mut self, id: Uuid) -> Result<Value, SocketError> {
-        let receiver = match self.response_map.lock().await.get(&id) {
+        let receiver = match self.response_map.lock().unwrap().get(&id) {
             Some(channel) => channel.1.clone(),
             None => { return Err(SocketError::General("Error: Incorrect response ID")); }
         };
+        let _pending = PendingRequest::new(&self.response_map, id);
 
-        receiver.recv().await.map_err(|e| SocketError::Channel(e))
+        receiver.recv().await.map_err(SocketError::Channel)
     }
 }
 
@@ -95,70 +102,103 @@ impl Socket<UnixStream> {
     where P: AsRef<Path>, F: Fn(IncomingMessageV1) + 'static + Send,
     {
         let socket = UnixStream::connect(path).await?;
-        let response_map = Arc::new(Mutex::new(HashMap::new()));
-        let listening = Arc::new(Mutex::new(true));
-
-        let socket_wrapper = Socket {
-            socket: socket.clone(),
-            response_map: response_map.clone(),
-            listening: listening.clone()
-        };
-
-        async_std::task::spawn(async move {
-            listen(
-                socket,
-                response_map,
-                listening,
-                handler
-            ).await;
-        });
-
-        Ok(socket_wrapper)
+        let response_map = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        let listener = async_std::task::spawn(listen(
+            socket.clone(),
+            response_map.clone(),
+            handler
+        ));
+
+        crate::metrics::connected();
+        Ok(Socket {
+            socket,
+            response_map,
+            listener: Some(listener)
+        })
     }
 }
 
-async fn listen<F>(socket: UnixStream, map: Map, listening: Arc<Mutex<bool>>, handler: F) 
+async fn listen<F>(socket: UnixStream, map: Map, handler: F)
 where F: Fn(IncomingMessageV1) + 'static + Send,
 {
     let mut reader = BufReader::new(socket);
     let mut buf = String::with_capacity(1024);
 
-    while *listening.lock().await {
+    loop {
         match reader.read_line(&mut buf).await {
+            Ok(0) => break,
             Ok(_) => {
-                let mut response: Value = serde_json::from_str(buf.as_str()).unwrap();
-                if let Some(id) = response.get("id") {
-                    let id = Uuid::parse_str(id.as_str().unwrap()).unwrap();
-                    let sender = map.lock().await.get(&id).unwrap().0.clone();
-
-                    if let Some(response) = response.get_mut("data") {
-                        match sender.send(response.take()).await {
-                            Ok(_) => {},
-                            Err(e) => println!("Error sending response: {}", e)
+                match serde_json::from_str::<Value>(buf.as_str()) {
+                    Ok(mut response) => {
+                        if let Some(id) = response.get("id") {
+                            match id.as_str().and_then(|id| Uuid::parse_str(id).ok()) {
+                                Some(id) => respond(&map, id, response),
+                                None => warn!("Response packet has an invalid id"),
+                            }
+                        } else {
+                            debug!("Received {} message", response.get("type").and_then(|msg| msg.as_str()).unwrap_or_default());
+                            if let Some(msg) = response.get_mut("data") {
+                                let msg = serde_json::from_value::<IncomingMessageV1>(
+                                    msg.take()
+                                );
+
+                                if let Ok(msg) = msg {
+                                    crate::metrics::message_received(msg.account.as_deref());
+                                    (handler)(msg);
+                                }
+                            }
                         }
-                    } else {
-                        println!("Response packet doesn't have data field");
-                    }
-                } else {
-                    println!("RECEIVED MESSAGE: {}", response.get("type").map(|msg| msg.as_str().unwrap_or_default()).unwrap_or_default());
-                    if let Some(msg) = response.get_mut("data") {
-                        let msg = serde_json::from_value::<IncomingMessageV1>(
-                            msg.take()
-                        );
-
-                        if let Ok(msg) = msg {
-                            (handler)(msg);
-                        }
-                    }
+                    },
+                    Err(e) => warn!("Failed to parse packet: {}", e)
                 }
             },
             Err(e) => {
-                println!("Error: {}", e.to_string());
+                error!("Failed to read from socket: {}", e);
             }
         }
 
         buf.clear();
     }
+
+    // Nothing will answer the requests still waiting, so close their channels to fail them
+    // instead of leaving them hanging. Responses that already arrived can still be received.
+    for (sender, _) in map.lock().unwrap().values() {
+        sender.close();
+    }
+}
+
+/// Hand a response to the request waiting on it. Responses to requests that were cancelled, or
+/// that never existed, are dropped.
+fn respond(map: &Map, id: Uuid, mut response: Value) {
+    let sender = match map.lock().unwrap().get(&id) {
+        Some(channel) => channel.0.clone(),
+        None => {
+            debug!("Dropping response to unknown request {}", id);
+            return;
+        }
+    };
+
+    // Errors are reported on the packet itself rather than in the data field
+    let response = match response.get("error") {
+        Some(_) => response,
+        None => response.get_mut("data").map(Value::take).unwrap_or_default()
+    };
+
+    if let Err(e) = sender.try_send(response) {
+        warn!("Failed to deliver response to request {}: {}", id, e);
+    }
+}
+
+pub(crate) async fn sleep(duration: std::time::Duration) {
+    async_std::task::sleep(duration).await;
+}
+
+impl<T> Drop for Socket<T> {
+    fn drop(&mut self) {
+        if let Some(listener) = self.listener.take() {
+            async_std::task::spawn(listener.cancel());
+        }
+    }
 }
 
 pub type Signald = SocketWrapper<Socket<UnixStream>>;
@@ -167,8 +207,6 @@ impl Signald {
     pub async fn connect<P, F>(path: P, handler: F) -> Result<Self, SocketError>
     where P: AsRef<Path>, F: Fn(IncomingMessageV1) + 'static + Send,
     {
-        Ok(Signald {
-            socket: Socket::connect(path, handler).await?,
-        })
+        Ok(Signald::new(Socket::connect(path, handler).await?))
     }
 }
@@ -0,0 +1,247 @@
+//! Building attachments for outgoing messages.
+//!
+//! Signald reads attachments from its own filesystem, so a [`JsonAttachmentV1`] only needs the
+//! path of the file. [`AttachmentBuilder`] fills in the rest locally: the MIME type is sniffed
+//! from the file's contents, and images get their dimensions and a blurhash preview. Attachments
+//! made from in-memory bytes are written to a directory signald can read, and removed again when
+//! the [`Attachment`] is dropped.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use image::DynamicImage;
+use uuid::Uuid;
+
+use crate::types::JsonAttachmentV1;
+use crate::SocketError;
+
+/// Blurhash components along each axis, the same as the Signal apps use
+const BLURHASH_COMPONENTS: (u32, u32) = (4, 3);
+
+/// Images are scaled down to fit this size before computing their blurhash
+const BLURHASH_SIZE: u32 = 64;
+
+enum Source {
+    Path(PathBuf),
+    Bytes(Vec<u8>)
+}
+
+pub struct AttachmentBuilder {
+    source: Source,
+    directory: PathBuf,
+    attachment: JsonAttachmentV1
+}
+
+impl AttachmentBuilder {
+    /// An attachment from a file. The path is made absolute, and must be readable by signald.
+    pub fn path<P: AsRef<Path>>(path: P) -> Self {
+        Self::new(Source::Path(path.as_ref().to_path_buf()))
+    }
+
+    /// An attachment from bytes in memory, which are written to a file when it is built
+    pub fn bytes<B: Into<Vec<u8>>>(bytes: B) -> Self {
+        Self::new(Source::Bytes(bytes.into()))
+    }
+
+    fn new(source: Source) -> Self {
+        AttachmentBuilder {
+            source,
+            directory: std::env::temp_dir(),
+            attachment: JsonAttachmentV1::default()
+        }
+    }
+
+    /// Where to write attachments made from bytes. Defaults to the system temporary directory,
+    /// which only works if signald runs on the same machine without a private `/tmp`.
+    pub fn directory<P: AsRef<Path>>(mut self, directory: P) -> Self {
+        self.directory = directory.as_ref().to_path_buf();
+        self
+    }
+
+    /// Override the sniffed MIME type
+    pub fn content_type<S: Into<String>>(mut self, content_type: S) -> Self {
+        self.attachment.content_type = Some(content_type.into());
+        self
+    }
+
+    /// The filename shown to recipients
+    pub fn custom_filename<S: Into<String>>(mut self, filename: S) -> Self {
+        self.attachment.custom_filename = Some(filename.into());
+        self
+    }
+
+    pub fn caption<S: Into<String>>(mut self, caption: S) -> Self {
+        self.attachment.caption = Some(caption.into());
+        self
+    }
+
+    pub fn voice_note(mut self, voice_note: bool) -> Self {
+        self.attachment.voice_note = Some(voice_note);
+        self
+    }
+
+    pub fn build(self) -> Result<Attachment, SocketError> {
+        let mut attachment = self.attachment;
+
+        // Only the start of a file is read to sniff its type
+        let (path, kind, temporary) = match &self.source {
+            Source::Path(path) => (fs::canonicalize(path)?, infer::get_from_path(path)?, false),
+            Source::Bytes(bytes) => {
+                let kind = infer::get(bytes);
+                let extension = kind.as_ref().map(|kind| kind.extension()).unwrap_or("bin");
+                let path = self.directory.join(format!("signald-{}.{}", Uuid::new_v4(), extension));
+                fs::write(&path, bytes)?;
+                (fs::canonicalize(path)?, kind, true)
+            }
+        };
+
+        if attachment.content_type.is_none() {
+            attachment.content_type = Some(kind
+                .map(|kind| kind.mime_type())
+                .unwrap_or("application/octet-stream")
+                .to_owned());
+        }
+
+        if attachment.content_type.as_deref().is_some_and(|content_type| content_type.starts_with("image/")) {
+            let image = match &self.source {
+                Source::Path(_) => decode(&path),
+                Source::Bytes(bytes) => image::load_from_memory(bytes)
+            };
+            match image {
+                Ok(image) => {
+                    attachment.width = Some(image.width() as i32);
+                    attachment.height = Some(image.height() as i32);
+                    attachment.blurhash = blurhash(&image);
+                },
+                Err(e) => { debug!("Failed to decode image attachment: {}", e); }
+            }
+        }
+
+        attachment.filename = Some(path.to_string_lossy().into_owned());
+        Ok(Attachment {
+            attachment,
+            temporary: if temporary { Some(path) } else { None }
+        })
+    }
+}
+
+fn decode(path: &Path) -> image::ImageResult<DynamicImage> {
+    image::io::Reader::open(path)?.with_guessed_format()?.decode()
+}
+
+fn blurhash(image: &DynamicImage) -> Option<String> {
+    let thumbnail = image.thumbnail(BLURHASH_SIZE, BLURHASH_SIZE).to_rgba8();
+    let (x, y) = BLURHASH_COMPONENTS;
+    blurhash::encode(x, y, thumbnail.width(), thumbnail.height(), thumbnail.as_raw()).ok()
+}
+
+/// A built attachment. If its file was written by the builder, the file is removed when this is
+/// dropped, so send it with
+/// [`send_with_attachments`](crate::actions::SocketWrapper::send_with_attachments), which keeps it
+/// until signald has responded.
+pub struct Attachment {
+    attachment: JsonAttachmentV1,
+    temporary: Option<PathBuf>
+}
+
+impl Attachment {
+    pub fn json(&self) -> &JsonAttachmentV1 {
+        &self.attachment
+    }
+
+    /// The path signald will read the attachment from
+    pub fn path(&self) -> &str {
+        self.attachment.filename.as_deref().unwrap_or_default()
+    }
+}
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        if let Some(path) = self.temporary.take() {
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Failed to remove attachment {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+impl<T: crate::socket::AsyncSocket + Send> crate::actions::SocketWrapper<T> {
+    /// Send a message with attachments, removing any temporary files once signald has
+    /// responded
+    pub async fn send_with_attachments(&mut self, mut request: crate::types::SendRequestV1, attachments: Vec<Attachment>) -> Result<crate::types::SendResponseV1, SocketError> {
+        request.attachments.get_or_insert_with(Vec::new)
+            .extend(attachments.iter().map(|attachment| attachment.attachment.clone()));
+        self.send(request, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::Path;
+    use image::{ImageOutputFormat, Rgb, RgbImage};
+    use super::AttachmentBuilder;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, _| Rgb([(x * 8) as u8, 64, 128]));
+        let mut buf = Cursor::new(Vec::new());
+        image.write_to(&mut buf, ImageOutputFormat::Png).unwrap();
+        buf.into_inner()
+    }
+
+    #[test]
+    fn image_bytes() {
+        let attachment = AttachmentBuilder::bytes(png(32, 16)).caption("gradient").build().unwrap();
+        let json = attachment.json();
+        assert_eq!(json.content_type.as_deref(), Some("image/png"));
+        assert_eq!((json.width, json.height), (Some(32), Some(16)));
+        assert!(json.blurhash.is_some());
+        assert!(attachment.path().ends_with(".png"));
+
+        let path = attachment.path().to_owned();
+        assert!(Path::new(&path).exists());
+        drop(attachment);
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn existing_file() {
+        let path = std::env::temp_dir().join(format!("signald-attachment-{}.txt", std::process::id()));
+        std::fs::write(&path, "plain text").unwrap();
+
+        let attachment = AttachmentBuilder::path(&path).build().unwrap();
+        assert_eq!(attachment.json().content_type.as_deref(), Some("application/octet-stream"));
+        assert!(attachment.json().width.is_none());
+
+        // Files that weren't written by the builder are left alone
+        drop(attachment);
+        assert!(path.exists());
+
+        // Images are recognized by their contents, not the extension
+        std::fs::write(&path, png(8, 4)).unwrap();
+        let attachment = AttachmentBuilder::path(&path).build().unwrap();
+        assert_eq!(attachment.json().content_type.as_deref(), Some("image/png"));
+        assert_eq!((attachment.json().width, attachment.json().height), (Some(8), Some(4)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+    #[test]
+    fn send_with_attachments() -> Result<(), crate::SocketError> {
+        crate::test_support::block_on(async {
+            let attachment = AttachmentBuilder::bytes(png(4, 4)).caption("square").build()?;
+            let path = attachment.path().to_owned();
+
+            let mut socket = crate::actions::SocketWrapper::new(crate::test_support::ScriptedSocket::recording());
+            socket.send_with_attachments(crate::types::SendRequestV1::default(), vec![attachment]).await?;
+
+            let sent = &socket.socket.sent[0]["attachments"][0];
+            assert_eq!(sent["caption"], "square");
+            assert_eq!(sent["filename"], path.as_str());
+            // Removed once signald has responded
+            assert!(!Path::new(&path).exists());
+            Ok(())
+        })
+    }
+}
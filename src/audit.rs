@@ -0,0 +1,277 @@
+//! Auditing identity keys between runs.
+//!
+//! [`IdentityAuditor`] keeps the identity keys of each account from `get_all_identities` in a
+//! file. Every audit compares the current keys with the previous snapshot, and logs new keys,
+//! trust level changes and removed keys as [`IdentityEvent`]s, which [`IdentityAuditor::report`]
+//! summarizes for review. The first audit of an account only records the snapshot to compare
+//! later audits with.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+use crate::actions::SocketWrapper;
+use crate::socket::AsyncSocket;
+use crate::address::same_address;
+use crate::types::{AllIdentityKeyListV1, GetAllIdentitiesV1, JsonAddressV1};
+use crate::util::{load_json, now, save_json};
+use crate::SocketError;
+
+/// An identity key as seen in a snapshot
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct KeyRecord {
+    pub number: Option<String>,
+    pub uuid: Option<String>,
+    pub safety_number: String,
+    pub trust_level: Option<String>,
+    /// When the key was first seen, in milliseconds since the unix epoch
+    pub added: Option<i64>
+}
+
+impl KeyRecord {
+    /// Whether both records are the same key of the same contact
+    fn same_key(&self, other: &KeyRecord) -> bool {
+        self.safety_number == other.safety_number && same_address(&self.address(), &other.address())
+    }
+
+    fn address(&self) -> JsonAddressV1 {
+        JsonAddressV1 {
+            number: self.number.clone(),
+            uuid: self.uuid.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn contact(&self) -> &str {
+        self.number.as_deref().or(self.uuid.as_deref()).unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum IdentityEvent {
+    NewKey {
+        key: KeyRecord
+    },
+    TrustChanged {
+        key: KeyRecord,
+        previous: Option<String>
+    },
+    Removed {
+        key: KeyRecord
+    }
+}
+
+impl Display for IdentityEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let level = |level: &Option<String>| level.clone().unwrap_or_else(|| "unknown".to_owned());
+
+        match self {
+            IdentityEvent::NewKey { key } => write!(f, "New key for {} ({}), safety number {}", key.contact(), level(&key.trust_level), key.safety_number),
+            IdentityEvent::TrustChanged { key, previous } => write!(f, "Trust level of {}'s key changed from {} to {}", key.contact(), level(previous), level(&key.trust_level)),
+            IdentityEvent::Removed { key } => write!(f, "Key for {} removed, safety number {}", key.contact(), key.safety_number)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoggedEvent {
+    pub account: String,
+    /// When the audit found the change, in milliseconds since the unix epoch
+    pub seen_at: u64,
+    #[serde(flatten)]
+    pub event: IdentityEvent
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct AuditLog {
+    snapshots: HashMap<String, Vec<KeyRecord>>,
+    events: Vec<LoggedEvent>
+}
+
+/// The changes logged since a point in time, which displays as one line per change
+pub struct Report {
+    pub since: u64,
+    pub events: Vec<LoggedEvent>
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.events.is_empty() {
+            return writeln!(f, "No identity key changes");
+        }
+
+        for logged in &self.events {
+            writeln!(f, "[{}] {}: {}", logged.seen_at, logged.account, logged.event)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct IdentityAuditor {
+    path: PathBuf,
+    log: AuditLog
+}
+
+impl IdentityAuditor {
+    /// An auditor keeping its snapshots and log at `path`, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SocketError> {
+        let path = path.as_ref().to_path_buf();
+        let log = load_json(&path, "Failed to parse identity audit file")?.unwrap_or_default();
+
+        Ok(IdentityAuditor { path, log })
+    }
+
+    /// Fetch the identity keys of `account` and record the changes since the last audit
+    pub async fn audit<T>(&mut self, socket: &mut SocketWrapper<T>, account: &str) -> Result<Vec<IdentityEvent>, SocketError>
+    where T: AsyncSocket + Send,
+    {
+        let identities = socket.get_all_identities(GetAllIdentitiesV1 {
+            account: Some(account.to_owned())
+        }, None).await?;
+
+        self.record(account, &identities)
+    }
+
+    /// Record a snapshot of the identity keys of `account`, returning the changes since the
+    /// previous one
+    pub fn record(&mut self, account: &str, identities: &AllIdentityKeyListV1) -> Result<Vec<IdentityEvent>, SocketError> {
+        let snapshot = snapshot(identities);
+        let events = match self.log.snapshots.get(account) {
+            Some(previous) => diff(previous, &snapshot),
+            None => Vec::new()
+        };
+
+        let seen_at = now();
+        for event in &events {
+            debug!("Identity change for {}: {}", account, event);
+            self.log.events.push(LoggedEvent {
+                account: account.to_owned(),
+                seen_at,
+                event: event.clone()
+            });
+        }
+        self.log.snapshots.insert(account.to_owned(), snapshot);
+        self.save()?;
+
+        Ok(events)
+    }
+
+    /// The keys of `account` as of its last audit
+    pub fn snapshot(&self, account: &str) -> Option<&[KeyRecord]> {
+        self.log.snapshots.get(account).map(Vec::as_slice)
+    }
+
+    /// Every change logged, oldest first
+    pub fn events(&self) -> &[LoggedEvent] {
+        &self.log.events
+    }
+
+    /// The changes logged since `since`, in milliseconds since the unix epoch
+    pub fn report(&self, since: u64) -> Report {
+        Report {
+            since,
+            events: self.log.events.iter()
+                .filter(|logged| logged.seen_at >= since)
+                .cloned()
+                .collect()
+        }
+    }
+
+    fn save(&self) -> Result<(), SocketError> {
+        save_json(&self.path, &self.log)
+    }
+}
+
+fn snapshot(identities: &AllIdentityKeyListV1) -> Vec<KeyRecord> {
+    let mut records = Vec::new();
+
+    for list in identities.identity_keys.iter().flatten() {
+        let address = list.address.clone().unwrap_or_default();
+        for identity in list.identities.iter().flatten() {
+            if let Some(safety_number) = &identity.safety_number {
+                records.push(KeyRecord {
+                    number: address.number.clone(),
+                    uuid: address.uuid.clone(),
+                    safety_number: safety_number.clone(),
+                    trust_level: identity.trust_level.clone(),
+                    added: identity.added
+                });
+            }
+        }
+    }
+
+    records
+}
+
+fn diff(previous: &[KeyRecord], current: &[KeyRecord]) -> Vec<IdentityEvent> {
+    let mut events = Vec::new();
+
+    for key in current {
+        match previous.iter().find(|old| old.same_key(key)) {
+            None => events.push(IdentityEvent::NewKey { key: key.clone() }),
+            Some(old) if old.trust_level != key.trust_level => events.push(IdentityEvent::TrustChanged {
+                key: key.clone(),
+                previous: old.trust_level.clone()
+            }),
+            Some(_) => {}
+        }
+    }
+
+    for key in previous {
+        if !current.iter().any(|new| new.same_key(key)) {
+            events.push(IdentityEvent::Removed { key: key.clone() });
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::{IdentityAuditor, IdentityEvent};
+    use crate::types::AllIdentityKeyListV1;
+    use crate::SocketError;
+
+    fn identities(keys: &[(&str, &str, &str)]) -> AllIdentityKeyListV1 {
+        let lists: Vec<_> = keys.iter()
+            .map(|(number, safety_number, trust_level)| json!({
+                "address": { "number": number },
+                "identities": [{ "safety_number": safety_number, "trust_level": trust_level, "added": 1 }]
+            }))
+            .collect();
+        serde_json::from_value(json!({ "identity_keys": lists })).unwrap()
+    }
+
+    #[test]
+    fn diffs_snapshots() -> Result<(), SocketError> {
+        let path = std::env::temp_dir().join(format!("signald-audit-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let account = "+15551234567";
+
+        let mut auditor = IdentityAuditor::open(&path)?;
+        let first = identities(&[("+15550000001", "111", "TRUSTED_UNVERIFIED"), ("+15550000002", "222", "TRUSTED_UNVERIFIED")]);
+        assert!(auditor.record(account, &first)?.is_empty());
+
+        // A later run sees a verification, a reinstall and a removed contact
+        let mut auditor = IdentityAuditor::open(&path)?;
+        let second = identities(&[("+15550000001", "111", "TRUSTED_VERIFIED"), ("+15550000003", "333", "UNTRUSTED")]);
+        let events = auditor.record(account, &second)?;
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[0], IdentityEvent::TrustChanged { previous: Some(previous), .. } if previous == "TRUSTED_UNVERIFIED"));
+        assert!(matches!(&events[1], IdentityEvent::NewKey { key } if key.safety_number == "333"));
+        assert!(matches!(&events[2], IdentityEvent::Removed { key } if key.number.as_deref() == Some("+15550000002")));
+
+        let auditor = IdentityAuditor::open(&path)?;
+        assert_eq!(auditor.snapshot(account).unwrap().len(), 2);
+        let report = auditor.report(0).to_string();
+        assert_eq!(report.lines().count(), 3);
+        assert!(report.contains("+15550000001's key changed from TRUSTED_UNVERIFIED to TRUSTED_VERIFIED"));
+        assert!(auditor.report(u64::MAX).events.is_empty());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
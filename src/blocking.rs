@@ -0,0 +1,186 @@
+use std::os::unix::net::UnixStream;
+use std::io::{BufRead, BufReader, Error, Write};
+use std::collections::VecDeque;
+use std::fmt::{Debug, Display};
+use std::path::Path;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::errors::SignaldError;
+use crate::types::IncomingMessageV1;
+
+pub enum SocketError {
+    General(&'static str),
+    Io(Error),
+    Signald(SignaldError)
+}
+
+impl Display for SocketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketError::General(desc) => write!(f, "Error: {}", desc),
+            SocketError::Io(e) => write!(f, "Error: {}", e),
+            SocketError::Signald(e) => write!(f, "Signald error: {}", e.error.message)
+        }
+    }
+}
+
+impl Debug for SocketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketError::General(desc) => write!(f, "Error: {}", desc),
+            SocketError::Io(e) => write!(f, "Error: {}", e),
+            SocketError::Signald(e) => write!(f, "Signald error: {}", e.error.message)
+        }
+    }
+}
+
+impl From<Error> for SocketError {
+    fn from(e: Error) -> Self {
+        SocketError::Io(e)
+    }
+}
+
+/// Synchronous signald client. Requests block until the matching response arrives, and
+/// any incoming messages read in the meantime are queued for [`Client::incoming`].
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    pending: VecDeque<IncomingMessageV1>
+}
+
+impl Client {
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self, SocketError> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+
+        crate::metrics::connected();
+        Ok(Client {
+            reader,
+            writer,
+            pending: VecDeque::new()
+        })
+    }
+
+    /// Iterate over incoming messages, blocking until each one arrives. Ends when signald
+    /// closes the socket.
+    pub fn incoming(&mut self) -> Incoming<'_> {
+        Incoming { client: self }
+    }
+
+    pub(crate) fn call(&mut self, buf: &[u8], id: &Uuid) -> Result<Value, SocketError> {
+        self.writer.write_all(buf)?;
+
+        let id = id.to_simple().to_string();
+        loop {
+            let mut response = match self.read_packet()? {
+                Some(response) => response,
+                None => { return Err(SocketError::General("Socket closed before response")); }
+            };
+
+            match response.get("id").and_then(|response_id| response_id.as_str()) {
+                Some(response_id) if response_id == id => {
+                    // Errors are reported on the packet itself rather than in the data field
+                    if response.get("error").is_some() {
+                        return Ok(response);
+                    }
+
+                    return Ok(response.get_mut("data").map(Value::take).unwrap_or_default());
+                },
+                Some(response_id) => debug!("Dropping response to unknown request {}", response_id),
+                None => self.queue_incoming(response)
+            }
+        }
+    }
+
+    fn read_packet(&mut self) -> Result<Option<Value>, SocketError> {
+        let mut buf = String::with_capacity(1024);
+
+        loop {
+            if self.reader.read_line(&mut buf)? == 0 {
+                return Ok(None);
+            }
+
+            match serde_json::from_str(buf.as_str()) {
+                Ok(packet) => { return Ok(Some(packet)); },
+                Err(e) => warn!("Failed to parse packet: {}", e)
+            }
+
+            buf.clear();
+        }
+    }
+
+    fn queue_incoming(&mut self, mut packet: Value) {
+        if let Some(msg) = packet.get_mut("data") {
+            if let Ok(msg) = serde_json::from_value::<IncomingMessageV1>(msg.take()) {
+                crate::metrics::message_received(msg.account.as_deref());
+                self.pending.push_back(msg);
+            }
+        }
+    }
+}
+
+/// Blocking iterator over incoming messages, returned by [`Client::incoming`].
+pub struct Incoming<'a> {
+    client: &'a mut Client
+}
+
+impl Iterator for Incoming<'_> {
+    type Item = Result<IncomingMessageV1, SocketError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(msg) = self.client.pending.pop_front() {
+                return Some(Ok(msg));
+            }
+
+            match self.client.read_packet() {
+                Ok(Some(packet)) => {
+                    if packet.get("id").is_none() {
+                        self.client.queue_incoming(packet);
+                    }
+                },
+                Ok(None) => { return None; },
+                Err(e) => { return Some(Err(e)); }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::io::{BufRead, BufReader, Write};
+    use serde_json::Value;
+    use super::{Client, SocketError};
+    use crate::types::VersionRequestV1;
+
+    #[test]
+    fn response_and_incoming() -> Result<(), SocketError> {
+        let path = std::env::temp_dir().join(format!("signald-blocking-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+            let request: Value = serde_json::from_str(&line).unwrap();
+
+            writeln!(stream, r#"{{"type":"IncomingMessage","data":{{"account":"+15551234567","timestamp":1}}}}"#).unwrap();
+            writeln!(stream, r#"{{"id":{},"type":"version","data":{{"name":"signald","version":"0.0.0"}}}}"#, request["id"]).unwrap();
+        });
+
+        let mut client = Client::connect(&path)?;
+        let version = client.version(VersionRequestV1, None)?;
+        assert_eq!(version.name.unwrap().as_str(), "signald");
+
+        server.join().unwrap();
+        let incoming: Vec<_> = client.incoming().collect::<Result<_, _>>()?;
+        assert_eq!(incoming.len(), 1);
+        assert_eq!(incoming[0].account.as_deref(), Some("+15551234567"));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,441 @@
+//! A small framework for command driven bots.
+//!
+//! A [`Bot`] receives incoming messages through the handler passed to `Signald::connect`, and
+//! routes the body of each data message to the first matching route: a `/command`, or a regex
+//! pattern. Route handlers are async functions taking a [`Context`], which carries the message,
+//! its sender and conversation, a handle to the client and the bot's shared state.
+//!
+//! Messages without a body, sync messages sent from our own devices, and messages from the bot's
+//! own account are ignored. Handlers that return an error are logged and answered with a
+//! configurable reply.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), signald::SocketError> {
+//! use signald::bot::Bot;
+//! use signald::Signald;
+//!
+//! let bot = Bot::new(())
+//!     .command("ping", |ctx| async move { ctx.reply("pong").await.map(|_| ()) });
+//! let signald = Signald::connect("/var/run/signald/signald.sock", bot.handler()).await?;
+//! bot.run(signald).await;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[cfg(feature = "async-std")]
+use async_std::{channel::{unbounded, Receiver, Sender}, sync::Mutex};
+#[cfg(feature = "smol")]
+use smol::{channel::{unbounded, Receiver, Sender}, lock::Mutex};
+
+use crate::actions::SocketWrapper;
+use crate::command::{self, BotCommand};
+use crate::conversation::Conversation;
+use crate::dialog::Dialogs;
+use crate::socket::AsyncSocket;
+use crate::types::{IncomingMessageV1, JsonAddressV1, SendResponseV1};
+use crate::SocketError;
+
+/// The client shared between handlers
+pub type Client<T> = Arc<Mutex<SocketWrapper<T>>>;
+
+type BoxFuture<O> = Pin<Box<dyn Future<Output = O> + Send>>;
+type Handler<S, T> = Box<dyn Fn(Context<S, T>) -> BoxFuture<Result<(), String>> + Send + Sync>;
+type ErrorReply = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+type Filter = Box<dyn Fn(&IncomingMessageV1) -> bool + Send + Sync>;
+
+enum Matcher {
+    Command(String),
+    Pattern(Regex),
+    /// The names of a [`BotCommand`], and `help`
+    Commands(fn() -> &'static [&'static str])
+}
+
+struct Route<S, T> {
+    matcher: Matcher,
+    handler: Handler<S, T>
+}
+
+/// Everything a handler knows about the message it was called for
+pub struct Context<S, T> {
+    pub message: IncomingMessageV1,
+    pub sender: JsonAddressV1,
+    pub conversation: Conversation,
+    /// For commands, the text after the command name. For patterns, the whole body.
+    pub args: String,
+    /// For patterns, the capture groups of the match, starting with the whole match
+    pub captures: Vec<Option<String>>,
+    pub client: Client<T>,
+    pub state: Arc<S>
+}
+
+impl<S, T> Context<S, T>
+where T: AsyncSocket + Send,
+{
+    /// Send `text` to the conversation the message came from
+    pub async fn reply<M: Into<String>>(&self, text: M) -> Result<SendResponseV1, SocketError> {
+        let reply = self.message.reply(text)
+            .ok_or(SocketError::General("Message can't be replied to"))?;
+        self.client.lock().await.send(reply, None).await
+    }
+}
+
+struct Router<S, T> {
+    filters: Vec<Filter>,
+    routes: Vec<Route<S, T>>,
+    state: Arc<S>,
+    error_reply: ErrorReply
+}
+
+pub struct Bot<S, T> {
+    router: Router<S, T>,
+    sender: Sender<IncomingMessageV1>,
+    receiver: Receiver<IncomingMessageV1>
+}
+
+impl<S, T> Bot<S, T>
+where S: Send + Sync + 'static,
+      T: AsyncSocket + Send + 'static,
+{
+    pub fn new(state: S) -> Self {
+        let (sender, receiver) = unbounded();
+
+        Bot {
+            router: Router {
+                filters: Vec::new(),
+                routes: Vec::new(),
+                state: Arc::new(state),
+                error_reply: Arc::new(|_| Some("Sorry, something went wrong.".to_owned()))
+            },
+            sender,
+            receiver
+        }
+    }
+
+    /// Route messages starting with `/name` to `handler`, with the rest of the message as
+    /// arguments
+    pub fn command<H, F, E>(self, name: &str, handler: H) -> Self
+    where H: Fn(Context<S, T>) -> F + Send + Sync + 'static,
+          F: Future<Output = Result<(), E>> + Send + 'static,
+          E: Display,
+    {
+        self.route(Matcher::Command(format!("/{}", name)), handler)
+    }
+
+    /// Route messages matching `pattern` anywhere in their body to `handler`
+    pub fn pattern<H, F, E>(self, pattern: Regex, handler: H) -> Self
+    where H: Fn(Context<S, T>) -> F + Send + Sync + 'static,
+          F: Future<Output = Result<(), E>> + Send + 'static,
+          E: Display,
+    {
+        self.route(Matcher::Pattern(pattern), handler)
+    }
+
+    fn route<H, F, E>(mut self, matcher: Matcher, handler: H) -> Self
+    where H: Fn(Context<S, T>) -> F + Send + Sync + 'static,
+          F: Future<Output = Result<(), E>> + Send + 'static,
+          E: Display,
+    {
+        self.router.routes.push(Route {
+            matcher,
+            handler: Box::new(move |ctx| {
+                let future = handler(ctx);
+                Box::pin(async move { future.await.map_err(|e| e.to_string()) })
+            })
+        });
+        self
+    }
+
+    /// Route the commands of `C` to `handler`, and answer `/help` with their help text. Commands
+    /// with invalid arguments are answered with a usage error instead of calling `handler`.
+    pub fn commands<C, H, F, E>(mut self, handler: H) -> Self
+    where C: BotCommand + 'static,
+          H: Fn(Context<S, T>, C) -> F + Send + Sync + 'static,
+          F: Future<Output = Result<(), E>> + Send + 'static,
+          E: Display,
+    {
+        self.router.routes.push(Route {
+            matcher: Matcher::Commands(C::names),
+            handler: Box::new(move |ctx| {
+                let body = ctx.message.data_message.as_ref()
+                    .and_then(|data| data.body.as_deref())
+                    .unwrap_or_default();
+
+                let reply = match command::parse::<C>(body) {
+                    Some(Ok(command)) => {
+                        let future = handler(ctx, command);
+                        return Box::pin(async move { future.await.map_err(|e| e.to_string()) });
+                    },
+                    Some(Err(e)) => e.to_string(),
+                    // Only `/help` gets here without being one of the commands
+                    None => C::help()
+                };
+
+                Box::pin(async move { ctx.reply(reply).await.map(|_| ()).map_err(|e| e.to_string()) })
+            })
+        });
+        self
+    }
+
+    /// Set the reply sent when a handler fails, given the error. Returning `None` sends
+    /// nothing.
+    pub fn error_reply<F>(mut self, error_reply: F) -> Self
+    where F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.router.error_reply = Arc::new(error_reply);
+        self
+    }
+
+    /// Offer every message to `filter` before routing it. Messages it returns `true` for are
+    /// considered handled, and aren't routed.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where F: Fn(&IncomingMessageV1) -> bool + Send + Sync + 'static,
+    {
+        self.router.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Deliver answers and cancel keywords to `dialogs` instead of routing them
+    pub fn dialogs<D>(self, dialogs: Dialogs<D>) -> Self
+    where D: Clone + Serialize + DeserializeOwned + Send + 'static,
+    {
+        self.filter(move |msg| dialogs.handle(msg))
+    }
+
+    /// The incoming message handler to connect with
+    pub fn handler(&self) -> impl Fn(IncomingMessageV1) + Send + 'static {
+        let sender = self.sender.clone();
+        move |msg| {
+            let _ = sender.try_send(msg);
+        }
+    }
+
+    /// Handle incoming messages until the connection is closed. Each handler runs in its own
+    /// task, so a handler waiting for an answer doesn't hold up other messages.
+    pub async fn run(self, socket: SocketWrapper<T>) {
+        let Bot { router, sender, receiver } = self;
+        // Only the handlers' senders should keep the channel open
+        drop(sender);
+        let client = Arc::new(Mutex::new(socket));
+
+        while let Ok(msg) = receiver.recv().await {
+            if let Some(handle) = router.route(&client, msg) {
+                spawn(handle);
+            }
+        }
+    }
+
+    /// Route a single message and wait for its handler, e.g. for messages received some other
+    /// way than through [`handler`](Self::handler)
+    pub async fn dispatch(&self, client: &Client<T>, msg: IncomingMessageV1) {
+        if let Some(handle) = self.router.route(client, msg) {
+            handle.await;
+        }
+    }
+}
+
+impl<S, T> Router<S, T>
+where S: Send + Sync + 'static,
+      T: AsyncSocket + Send + 'static,
+{
+    /// Find the route for `msg`, returning the future that runs its handler
+    fn route(&self, client: &Client<T>, msg: IncomingMessageV1) -> Option<BoxFuture<()>> {
+        if msg.is_own() || self.filters.iter().any(|filter| filter(&msg)) {
+            return None;
+        }
+
+        let (body, sender, conversation) = match (msg.data_message.as_ref().and_then(|data| data.body.clone()), msg.source.clone(), msg.conversation()) {
+            (Some(body), Some(sender), Some(conversation)) => (body, sender, conversation),
+            _ => { return None; }
+        };
+
+        for route in &self.routes {
+            let (args, captures) = match &route.matcher {
+                Matcher::Command(command) => match command_args(&body, command) {
+                    Some(args) => (args.to_owned(), Vec::new()),
+                    None => continue
+                },
+                Matcher::Pattern(pattern) => match pattern.captures(&body) {
+                    Some(captures) => (
+                        body.clone(),
+                        captures.iter().map(|capture| capture.map(|m| m.as_str().to_owned())).collect()
+                    ),
+                    None => continue
+                },
+                Matcher::Commands(names) => match command::split_name(&body) {
+                    Some((name, rest)) if name == "help" || names().contains(&name) => (rest.trim().to_owned(), Vec::new()),
+                    _ => continue
+                }
+            };
+
+            let ctx = Context {
+                message: msg.clone(),
+                sender,
+                conversation,
+                args,
+                captures,
+                client: client.clone(),
+                state: self.state.clone()
+            };
+
+            let handle = (route.handler)(ctx);
+            let error_reply = self.error_reply.clone();
+            let client = client.clone();
+
+            return Some(Box::pin(async move {
+                if let Err(e) = handle.await {
+                    error!("Bot handler failed: {}", e);
+                    if let Some(reply) = error_reply(&e).and_then(|text| msg.reply(text)) {
+                        if let Err(e) = client.lock().await.send(reply, None).await {
+                            error!("Failed to send error reply: {}", e);
+                        }
+                    }
+                }
+            }));
+        }
+
+        None
+    }
+}
+
+#[cfg(feature = "async-std")]
+fn spawn(future: BoxFuture<()>) {
+    async_std::task::spawn(future);
+}
+
+#[cfg(feature = "smol")]
+fn spawn(future: BoxFuture<()>) {
+    smol::spawn(future).detach();
+}
+
+/// The arguments of `body` if it is the command `command`
+fn command_args<'a>(body: &'a str, command: &str) -> Option<&'a str> {
+    let rest = body.trim_start().strip_prefix(command)?;
+    match rest.chars().next() {
+        None => Some(""),
+        Some(c) if c.is_whitespace() => Some(rest.trim()),
+        Some(_) => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::{Bot, Mutex};
+    use regex::Regex;
+    use crate::actions::SocketWrapper;
+    use crate::command::{Args, BotCommand, UsageError};
+    use crate::test_support::{block_on, timeout, ScriptedSocket};
+    use crate::types::{IncomingMessageV1, JsonAddressV1, JsonDataMessageV1, JsonSyncMessageV1};
+
+    fn message(source: &str, body: &str) -> IncomingMessageV1 {
+        IncomingMessageV1 {
+            account: Some("+15550000000".to_owned()),
+            source: Some(JsonAddressV1 {
+                number: Some(source.to_owned()),
+                ..Default::default()
+            }),
+            data_message: Some(JsonDataMessageV1 {
+                body: Some(body.to_owned()),
+                timestamp: Some(100),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn routes() {
+        block_on(async {
+            let bot = Bot::new(AtomicUsize::new(0))
+                .command("echo", |ctx| async move { ctx.reply(ctx.args.clone()).await.map(|_| ()) })
+                .command("fail", |_| async { Err("broken") })
+                .pattern(Regex::new(r"(\d+) \+ (\d+)").unwrap(), |ctx| async move {
+                    ctx.state.fetch_add(1, Ordering::SeqCst);
+                    let a: u32 = ctx.captures[1].as_ref().unwrap().parse().unwrap();
+                    let b: u32 = ctx.captures[2].as_ref().unwrap().parse().unwrap();
+                    ctx.reply((a + b).to_string()).await.map(|_| ())
+                })
+                .error_reply(|e| Some(format!("failed: {}", e)));
+            let client = Arc::new(Mutex::new(SocketWrapper::new(ScriptedSocket::recording())));
+
+            bot.dispatch(&client, message("+15550000001", "/echo hello world")).await;
+            bot.dispatch(&client, message("+15550000001", "/echoes")).await;
+            bot.dispatch(&client, message("+15550000001", "what is 2 + 3?")).await;
+            bot.dispatch(&client, message("+15550000001", "/fail")).await;
+            // Our own messages never trigger routes
+            bot.dispatch(&client, message("+15550000000", "/echo me")).await;
+            bot.dispatch(&client, IncomingMessageV1 {
+                sync_message: Some(JsonSyncMessageV1::default()),
+                ..message("+15550000001", "/echo sync")
+            }).await;
+
+            let replies: Vec<_> = client.lock().await.socket.sent.iter()
+                .map(|sent| sent["messageBody"].as_str().unwrap().to_owned())
+                .collect();
+            assert_eq!(replies, ["hello world", "5", "failed: broken"]);
+            assert_eq!(bot.router.state.load(Ordering::SeqCst), 1);
+        })
+    }
+
+    struct Add(u32, u32);
+
+    impl BotCommand for Add {
+        fn names() -> &'static [&'static str] {
+            &["add"]
+        }
+
+        fn parse_args(_name: &str, args: &[String]) -> Result<Self, UsageError> {
+            let mut args = Args::new(args, "/add <a> <b>");
+            let add = Add(args.next("a")?, args.next("b")?);
+            args.finish()?;
+            Ok(add)
+        }
+
+        fn help() -> String {
+            "/add <a> <b>".to_owned()
+        }
+    }
+
+    #[test]
+    fn typed_commands() {
+        block_on(async {
+            let bot = Bot::new(())
+                .commands(|ctx, Add(a, b)| async move { ctx.reply((a + b).to_string()).await.map(|_| ()) });
+            let client = Arc::new(Mutex::new(SocketWrapper::new(ScriptedSocket::recording())));
+
+            for body in ["/add 1 2", "/add 1", "/help", "/sub 1 2"] {
+                bot.dispatch(&client, message("+15550000001", body)).await;
+            }
+
+            let replies: Vec<_> = client.lock().await.socket.sent.iter()
+                .map(|sent| sent["messageBody"].as_str().unwrap().to_owned())
+                .collect();
+            assert_eq!(replies, ["3", "Missing b\nUsage: /add <a> <b>", "/add <a> <b>"]);
+        })
+    }
+
+    #[test]
+    fn run_until_closed() {
+        block_on(async {
+            let bot = Bot::new(())
+                .command("ping", |ctx| async move { ctx.reply("pong").await.map(|_| ()) });
+
+            let handler = bot.handler();
+            handler(message("+15550000001", "/ping"));
+            drop(handler);
+
+            let socket = SocketWrapper::new(ScriptedSocket::recording());
+            timeout(std::time::Duration::from_secs(1), bot.run(socket)).await
+                .expect("bot should stop once the handler is dropped");
+        })
+    }
+}
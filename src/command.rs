@@ -0,0 +1,250 @@
+//! Parsing `/command` messages into typed values.
+//!
+//! A [`BotCommand`] is usually an enum deriving it with the `derive` feature, with a variant for
+//! each command and a field for each argument:
+//!
+//! ```ignore
+//! #[derive(BotCommand)]
+//! enum Command {
+//!     /// Remind you about something later
+//!     Remind { after: Duration, text: String },
+//!     /// Roll a die, with 6 sides unless given
+//!     #[command(name = "dice")]
+//!     Roll { sides: Option<u32> }
+//! }
+//! ```
+//!
+//! Variants are named after the lowercased variant name unless renamed, and their doc comment is
+//! used in the help text. Arguments are separated by whitespace, and can be quoted with `"` or
+//! `'` to include whitespace, as in `/remind 10m "stand up"`. Fields are parsed with [`FromArg`],
+//! and `Option` fields may be left out.
+
+use std::fmt::Display;
+use std::time::Duration;
+
+#[cfg(feature = "derive")]
+pub use signald_derive::BotCommand;
+
+use crate::types::JsonDataMessageV1;
+
+pub trait BotCommand: Sized {
+    /// The names of the commands, without the leading `/`
+    fn names() -> &'static [&'static str];
+
+    /// Parse the arguments of the command `name`
+    fn parse_args(name: &str, args: &[String]) -> Result<Self, UsageError>;
+
+    /// A line for each command, with its arguments and description
+    fn help() -> String;
+}
+
+/// Parse `body` as one of the commands of `C`. Returns `None` if it isn't one of them, and a
+/// [`UsageError`] to show the sender if the arguments don't match.
+pub fn parse<C: BotCommand>(body: &str) -> Option<Result<C, UsageError>> {
+    let (name, rest) = split_name(body)?;
+    if !C::names().contains(&name) {
+        return None;
+    }
+
+    Some(split_args(rest).and_then(|args| C::parse_args(name, &args)))
+}
+
+/// Split a `/command` into its name and the rest of the message
+pub(crate) fn split_name(body: &str) -> Option<(&str, &str)> {
+    let body = body.trim().strip_prefix('/')?;
+    Some(match body.find(char::is_whitespace) {
+        Some(end) => (&body[..end], &body[end..]),
+        None => (body, "")
+    })
+}
+
+/// Split arguments at whitespace, keeping quoted arguments together. Backslashes escape the next
+/// character, inside or outside of quotes.
+pub fn split_args(args: &str) -> Result<Vec<String>, UsageError> {
+    let mut split = Vec::new();
+    let mut chars = args.chars();
+    let mut current: Option<String> = None;
+    let mut quote = None;
+
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => {
+                let escaped = chars.next().ok_or_else(|| UsageError::new("Nothing to escape at the end of the message"))?;
+                current.get_or_insert_with(String::new).push(escaped);
+            },
+            (c, Some(q)) if c == q => { quote = None; },
+            (c, Some(_)) => { current.get_or_insert_with(String::new).push(c); },
+            ('"' | '\'', None) => {
+                quote = Some(c);
+                current.get_or_insert_with(String::new);
+            },
+            (c, None) if c.is_whitespace() => split.extend(current.take()),
+            (c, None) => { current.get_or_insert_with(String::new).push(c); }
+        }
+    }
+
+    if quote.is_some() {
+        return Err(UsageError::new("Missing closing quote"));
+    }
+
+    split.extend(current);
+    Ok(split)
+}
+
+/// An explanation of what was wrong with a command, meant to be sent back to the sender
+#[derive(Debug, PartialEq, Eq)]
+pub struct UsageError {
+    pub message: String,
+    pub usage: Option<String>
+}
+
+impl UsageError {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        UsageError {
+            message: message.into(),
+            usage: None
+        }
+    }
+}
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.usage {
+            Some(usage) => write!(f, "{}\nUsage: {}", self.message, usage),
+            None => write!(f, "{}", self.message)
+        }
+    }
+}
+
+/// Types that can be parsed from a single command argument
+pub trait FromArg: Sized {
+    fn from_arg(arg: &str) -> Result<Self, String>;
+
+    /// The value to use when the argument is left out, if it is optional
+    fn missing() -> Option<Self> {
+        None
+    }
+}
+
+macro_rules! from_str_arg {
+    ($($type:ty),*) => {
+        $(
+            impl FromArg for $type {
+                fn from_arg(arg: &str) -> Result<Self, String> {
+                    arg.parse().map_err(|e| format!("{}", e))
+                }
+            }
+        )*
+    };
+}
+
+from_str_arg!(String, bool, char, i8, i16, i32, i64, u8, u16, u32, u64, usize, f32, f64);
+
+impl<T: FromArg> FromArg for Option<T> {
+    fn from_arg(arg: &str) -> Result<Self, String> {
+        T::from_arg(arg).map(Some)
+    }
+
+    fn missing() -> Option<Self> {
+        Some(None)
+    }
+}
+
+/// Durations like `90s`, `10m` or `1h30m`, with units `s`, `m`, `h` and `d`
+impl FromArg for Duration {
+    fn from_arg(arg: &str) -> Result<Self, String> {
+        let invalid = || format!("expected a duration like 10m or 1h30m, not {}", arg);
+        let mut seconds = 0u64;
+        let mut number = String::new();
+
+        for c in arg.chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+
+            let unit = match c {
+                's' => 1,
+                'm' => 60,
+                'h' => 60 * 60,
+                'd' => 24 * 60 * 60,
+                _ => { return Err(invalid()); }
+            };
+            let value: u64 = number.parse().map_err(|_| invalid())?;
+            seconds = value.checked_mul(unit)
+                .and_then(|value| seconds.checked_add(value))
+                .ok_or_else(invalid)?;
+            number.clear();
+        }
+
+        if !number.is_empty() || arg.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Duration::from_secs(seconds))
+    }
+}
+
+/// The arguments of a single command, used by the derived [`BotCommand`] implementations
+#[doc(hidden)]
+pub struct Args<'a> {
+    args: std::slice::Iter<'a, String>,
+    usage: &'static str
+}
+
+impl<'a> Args<'a> {
+    pub fn new(args: &'a [String], usage: &'static str) -> Self {
+        Args {
+            args: args.iter(),
+            usage
+        }
+    }
+
+    pub fn next<T: FromArg>(&mut self, name: &str) -> Result<T, UsageError> {
+        match self.args.next() {
+            Some(arg) => T::from_arg(arg).map_err(|e| self.error(format!("Invalid {}: {}", name, e))),
+            None => T::missing().ok_or_else(|| self.error(format!("Missing {}", name)))
+        }
+    }
+
+    pub fn finish(mut self) -> Result<(), UsageError> {
+        match self.args.next() {
+            Some(arg) => Err(self.error(format!("Unexpected argument {}", arg))),
+            None => Ok(())
+        }
+    }
+
+    fn error(&self, message: String) -> UsageError {
+        UsageError {
+            message,
+            usage: Some(self.usage.to_owned())
+        }
+    }
+}
+
+impl JsonDataMessageV1 {
+    /// Parse the body as one of the commands of `C`, see [`parse`]
+    pub fn command<C: BotCommand>(&self) -> Option<Result<C, UsageError>> {
+        parse(self.body.as_deref()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{split_args, FromArg};
+
+    #[test]
+    fn quoting() {
+        assert_eq!(split_args(r#"  10m "stand up"  it\'s 'a "b"' "" "#).unwrap(), ["10m", "stand up", "it's", "a \"b\"", ""]);
+        assert!(split_args("\"open").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(Duration::from_arg("1h30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(Duration::from_arg("90s"), Ok(Duration::from_secs(90)));
+        assert!(Duration::from_arg("10").is_err());
+        assert!(Duration::from_arg("m").is_err());
+    }
+}
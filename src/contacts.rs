@@ -0,0 +1,409 @@
+//! A local cache of contacts and profiles.
+//!
+//! [`ContactStore`] is seeded from `list_contacts` and fetches profiles it doesn't have, or
+//! has had for longer than its TTL, with `get_profile`. Offering incoming messages to
+//! [`ContactStore::handle`] keeps it current: profile key updates mark the sender's profile as
+//! stale, and contact syncs from the primary device mark the whole store for reseeding.
+//!
+//! Addresses are normalized before lookups. A contact first seen with only a number and later
+//! with only a UUID is kept as one entry once an address with both is seen, whether in a profile
+//! or as the source of an incoming message. All clones of a store share one cache, so updates seen
+//! by the handler show up in lookups made anywhere else.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::actions::SocketWrapper;
+use crate::address::normalize;
+use crate::socket::AsyncSocket;
+use crate::types::{GetProfileRequestV1, IncomingMessageV1, JsonAddressV1, ListContactsRequestV1, ProfileV1};
+use crate::SocketError;
+
+pub use crate::address::{normalize_number, normalize_uuid};
+
+/// How long profiles are used before they are fetched again, unless configured otherwise
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Default)]
+struct Entry {
+    number: Option<String>,
+    uuid: Option<String>,
+    profile: Option<ProfileV1>,
+    /// When the profile was fetched, or `None` if it is stale
+    fetched_at: Option<Instant>
+}
+
+struct Inner {
+    account: String,
+    ttl: Duration,
+    entries: HashMap<u64, Entry>,
+    numbers: HashMap<String, u64>,
+    uuids: HashMap<String, u64>,
+    next_id: u64,
+    needs_seed: bool
+}
+
+impl Inner {
+    fn find(&self, number: Option<&str>, uuid: Option<&str>) -> Option<u64> {
+        uuid.and_then(|uuid| self.uuids.get(uuid))
+            .or_else(|| number.and_then(|number| self.numbers.get(number)))
+            .copied()
+    }
+
+    fn get(&self, address: &JsonAddressV1) -> Option<&Entry> {
+        let (number, uuid) = normalize(address);
+        let id = self.find(number.as_deref(), uuid.as_deref())?;
+        self.entries.get(&id)
+    }
+
+    /// The entry for `address`, creating it or filling in the number or UUID it was missing.
+    /// Merges the entries of the number and the UUID if they were seen separately.
+    fn learn(&mut self, address: &JsonAddressV1) -> Option<u64> {
+        let (number, uuid) = normalize(address);
+        let by_number = number.as_ref().and_then(|number| self.numbers.get(number)).copied();
+        let by_uuid = uuid.as_ref().and_then(|uuid| self.uuids.get(uuid)).copied();
+
+        let id = match (by_number, by_uuid) {
+            (None, None) if number.is_none() && uuid.is_none() => { return None; },
+            (None, None) => {
+                self.next_id += 1;
+                self.entries.insert(self.next_id, Entry::default());
+                self.next_id
+            },
+            (Some(id), None) | (None, Some(id)) => id,
+            (Some(from), Some(into)) if from != into => {
+                self.merge(from, into);
+                into
+            },
+            (Some(id), Some(_)) => id
+        };
+
+        let entry = self.entries.get_mut(&id).unwrap();
+        if let Some(number) = number {
+            entry.number.get_or_insert_with(|| number.clone());
+            self.numbers.insert(number, id);
+        }
+        if let Some(uuid) = uuid {
+            entry.uuid.get_or_insert_with(|| uuid.clone());
+            self.uuids.insert(uuid, id);
+        }
+
+        Some(id)
+    }
+
+    /// Fold entry `from` into `into`, keeping the fresher profile
+    fn merge(&mut self, from: u64, into: u64) {
+        let from_entry = match self.entries.remove(&from) {
+            Some(entry) => entry,
+            None => { return; }
+        };
+
+        for id in self.numbers.values_mut().chain(self.uuids.values_mut()) {
+            if *id == from {
+                *id = into;
+            }
+        }
+
+        let entry = self.entries.get_mut(&into).unwrap();
+        entry.number = entry.number.take().or(from_entry.number);
+        entry.uuid = entry.uuid.take().or(from_entry.uuid);
+        if from_entry.fetched_at > entry.fetched_at || entry.profile.is_none() {
+            entry.profile = from_entry.profile;
+            entry.fetched_at = from_entry.fetched_at;
+        }
+    }
+
+    fn update(&mut self, profile: ProfileV1) {
+        let id = match profile.address.as_ref().and_then(|address| self.learn(address)) {
+            Some(id) => id,
+            None => { return; }
+        };
+
+        let entry = self.entries.get_mut(&id).unwrap();
+        entry.profile = Some(profile);
+        entry.fetched_at = Some(Instant::now());
+    }
+
+    fn is_fresh(&self, entry: &Entry) -> bool {
+        entry.profile.is_some() && entry.fetched_at.is_some_and(|fetched_at| fetched_at.elapsed() < self.ttl)
+    }
+}
+
+#[derive(Clone)]
+pub struct ContactStore {
+    inner: Arc<Mutex<Inner>>
+}
+
+impl ContactStore {
+    /// An empty store for `account`, which is seeded on first use
+    pub fn new<S: Into<String>>(account: S) -> Self {
+        ContactStore {
+            inner: Arc::new(Mutex::new(Inner {
+                account: account.into(),
+                ttl: DEFAULT_TTL,
+                entries: HashMap::new(),
+                numbers: HashMap::new(),
+                uuids: HashMap::new(),
+                next_id: 0,
+                needs_seed: true
+            }))
+        }
+    }
+
+    /// How long profiles are used before they are fetched again. Defaults to an hour.
+    pub fn ttl(self, ttl: Duration) -> Self {
+        self.inner.lock().unwrap().ttl = ttl;
+        self
+    }
+
+    /// Add the account's contacts from signald, refreshing the profiles of those already cached.
+    /// Profiles that aren't in the list, like those of non-contacts fetched on demand, are kept.
+    pub async fn seed<T>(&self, socket: &mut SocketWrapper<T>) -> Result<(), SocketError>
+    where T: AsyncSocket + Send,
+    {
+        let account = self.inner.lock().unwrap().account.clone();
+        let contacts = socket.list_contacts(ListContactsRequestV1 {
+            account: Some(account),
+            ..Default::default()
+        }, None).await?;
+
+        let mut inner = self.inner.lock().unwrap();
+        for profile in contacts.profiles.unwrap_or_default() {
+            inner.update(profile);
+        }
+        inner.needs_seed = false;
+        debug!("Seeded contact store with {} contacts", inner.entries.len());
+
+        Ok(())
+    }
+
+    /// The profile of `address`, fetching it if it isn't cached or has gone stale
+    pub async fn profile<T>(&self, socket: &mut SocketWrapper<T>, address: &JsonAddressV1) -> Result<ProfileV1, SocketError>
+    where T: AsyncSocket + Send,
+    {
+        if self.inner.lock().unwrap().needs_seed {
+            self.seed(socket).await?;
+        }
+
+        let account = {
+            let inner = self.inner.lock().unwrap();
+            if let Some(entry) = inner.get(address).filter(|entry| inner.is_fresh(entry)) {
+                return Ok(entry.profile.clone().unwrap());
+            }
+            inner.account.clone()
+        };
+
+        let mut profile = socket.get_profile(GetProfileRequestV1 {
+            account: Some(account),
+            address: Some(self.normalize(address)),
+            ..Default::default()
+        }, None).await?;
+        if profile.address.is_none() {
+            profile.address = Some(address.clone());
+        }
+
+        self.inner.lock().unwrap().update(profile.clone());
+        Ok(profile)
+    }
+
+    /// The cached profile of `address`, however old it is
+    pub fn get(&self, address: &JsonAddressV1) -> Option<ProfileV1> {
+        self.inner.lock().unwrap().get(address)?.profile.clone()
+    }
+
+    pub fn by_number(&self, number: &str) -> Option<ProfileV1> {
+        self.get(&JsonAddressV1 {
+            number: Some(number.to_owned()),
+            ..Default::default()
+        })
+    }
+
+    pub fn by_uuid(&self, uuid: &str) -> Option<ProfileV1> {
+        self.get(&JsonAddressV1 {
+            uuid: Some(uuid.to_owned()),
+            ..Default::default()
+        })
+    }
+
+    /// Cached profiles whose contact or profile name contains `name`, ignoring case
+    pub fn by_name(&self, name: &str) -> Vec<ProfileV1> {
+        let name = name.to_lowercase();
+        self.inner.lock().unwrap().entries.values()
+            .filter_map(|entry| entry.profile.as_ref())
+            .filter(|profile| {
+                [&profile.name, &profile.profile_name].iter()
+                    .any(|candidate| candidate.as_ref().is_some_and(|candidate| candidate.to_lowercase().contains(&name)))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The name to show for `address`: its contact or profile name if cached, or else its
+    /// number or UUID
+    pub fn display_name(&self, address: &JsonAddressV1) -> String {
+        let address = self.normalize(address);
+        let name = self.get(&address).and_then(|profile| {
+            profile.name.filter(|name| !name.is_empty())
+                .or(profile.profile_name.filter(|name| !name.is_empty()))
+        });
+
+        name.or(address.number)
+            .or(address.uuid)
+            .unwrap_or_default()
+    }
+
+    /// `address` in normalized form, with the number or UUID it is missing filled in if known
+    pub fn normalize(&self, address: &JsonAddressV1) -> JsonAddressV1 {
+        let (mut number, mut uuid) = normalize(address);
+        if let Some(entry) = self.inner.lock().unwrap().get(address) {
+            number = number.or_else(|| entry.number.clone());
+            uuid = uuid.or_else(|| entry.uuid.clone());
+        }
+
+        JsonAddressV1 {
+            number: number.or_else(|| address.number.clone()),
+            uuid: uuid.or_else(|| address.uuid.clone()),
+            relay: address.relay.clone()
+        }
+    }
+
+    /// Update the store from an incoming message. Returns `true` if it invalidated any cached
+    /// profiles.
+    pub fn handle(&self, msg: &IncomingMessageV1) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let id = msg.source.as_ref().and_then(|source| inner.learn(source));
+
+        if msg.sync_message.as_ref().is_some_and(|sync| sync.contacts.is_some()) {
+            debug!("Contacts were synced, reseeding contact store on next use");
+            inner.needs_seed = true;
+            for entry in inner.entries.values_mut() {
+                entry.fetched_at = None;
+            }
+            return true;
+        }
+
+        let profile_key_update = msg.data_message.as_ref()
+            .and_then(|data| data.profile_key_update)
+            .unwrap_or(false);
+        match (id, profile_key_update) {
+            (Some(id), true) => {
+                inner.entries.get_mut(&id).unwrap().fetched_at = None;
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use serde_json::{json, Value};
+    use super::{normalize_number, ContactStore};
+    use crate::actions::SocketWrapper;
+    use crate::test_support::{block_on, ScriptedSocket};
+    use crate::types::{IncomingMessageV1, JsonAddressV1, JsonDataMessageV1, JsonSyncMessageV1, JsonAttachmentV1};
+    use crate::SocketError;
+
+    const ALICE: &str = "3b1ee3ab-48c5-4c8d-9f4e-1d2a4a46a0d8";
+
+    fn address(number: Option<&str>, uuid: Option<&str>) -> JsonAddressV1 {
+        JsonAddressV1 {
+            number: number.map(str::to_owned),
+            uuid: uuid.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    fn contacts() -> Value {
+        json!({ "profiles": [
+            { "address": { "number": "+15550000001", "uuid": ALICE }, "name": "Alice Smith", "profile_name": "alice" },
+            { "address": { "number": "+15550000002" }, "profile_name": "Bob" }
+        ]})
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(normalize_number(" +1 (555) 000-0001 ").as_deref(), Some("+15550000001"));
+        assert_eq!(normalize_number("15550000001").as_deref(), Some("+15550000001"));
+        assert_eq!(normalize_number("+1555abc"), None);
+        assert_eq!(normalize_number("+"), None);
+    }
+
+    #[test]
+    fn lookups_and_lazy_refresh() -> Result<(), SocketError> {
+        block_on(async {
+            let store = ContactStore::new("+15551234567");
+            let mut socket = SocketWrapper::new(ScriptedSocket::new(vec![
+                    contacts(),
+                    json!({ "address": { "number": "+15550000003" }, "profile_name": "Carol" }),
+                    json!({ "address": { "number": "+15550000001", "uuid": ALICE }, "name": "Alice Jones" })
+                ]));
+
+            // Seeded on first use, then served from the cache
+            let alice = store.profile(&mut socket, &address(None, Some(&ALICE.to_uppercase()))).await?;
+            assert_eq!(alice.name.as_deref(), Some("Alice Smith"));
+            assert_eq!(socket.socket.sent.len(), 1);
+            assert_eq!(socket.socket.sent[0]["type"], "list_contacts");
+            assert_eq!(store.by_number("+1 555 000 0001").unwrap().name.as_deref(), Some("Alice Smith"));
+            assert_eq!(store.by_name("BOB").len(), 1);
+            assert_eq!(store.normalize(&address(Some("+15550000001"), None)).uuid.as_deref(), Some(ALICE));
+
+            // Unknown contacts are fetched
+            store.profile(&mut socket, &address(Some("+15550000003"), None)).await?;
+            assert_eq!(socket.socket.sent[1]["type"], "get_profile");
+            assert_eq!(store.display_name(&address(Some("+15550000003"), None)), "Carol");
+            assert_eq!(store.display_name(&address(Some("+15550000009"), None)), "+15550000009");
+
+            // A profile key update makes the next lookup refetch
+            let update = IncomingMessageV1 {
+                source: Some(address(None, Some(ALICE))),
+                data_message: Some(JsonDataMessageV1 {
+                    profile_key_update: Some(true),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            assert!(store.handle(&update));
+            let alice = store.profile(&mut socket, &address(Some("+15550000001"), None)).await?;
+            assert_eq!(alice.name.as_deref(), Some("Alice Jones"));
+            assert_eq!(socket.socket.sent.len(), 3);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn merges_addresses_and_reseeds() -> Result<(), SocketError> {
+        block_on(async {
+            let store = ContactStore::new("+15551234567").ttl(Duration::from_secs(60));
+            let mut socket = SocketWrapper::new(ScriptedSocket::new(vec![contacts(), contacts()]));
+            store.seed(&mut socket).await?;
+
+            // Bob is only known by number until a message shows his UUID
+            let bob = "5f0c4d4e-2b56-4b0b-9a0e-6f8d1d3c2b1a";
+            assert!(store.by_uuid(bob).is_none());
+            let message = IncomingMessageV1 {
+                source: Some(address(Some("+15550000002"), Some(bob))),
+                ..Default::default()
+            };
+            assert!(!store.handle(&message));
+            assert_eq!(store.by_uuid(bob).unwrap().profile_name.as_deref(), Some("Bob"));
+
+            let sync = IncomingMessageV1 {
+                sync_message: Some(JsonSyncMessageV1 {
+                    contacts: Some(JsonAttachmentV1::default()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            assert!(store.handle(&sync));
+            store.profile(&mut socket, &address(Some("+15550000002"), None)).await?;
+            assert_eq!(socket.socket.sent.len(), 2);
+            assert_eq!(socket.socket.sent[1]["type"], "list_contacts");
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,209 @@
+//! A single identifier for direct and group conversations.
+//!
+//! Requests that can go to either a person or a group have separate address and group fields.
+//! [`Conversation`] stands for either, and [`ConversationRequest`] fills in the right fields of
+//! such a request.
+
+use std::fmt::{Debug, Display};
+use std::hash::{Hash, Hasher};
+
+use crate::address;
+use crate::types::{
+    IncomingMessageV1, JsonAddressV1, ReactRequestV1, SendRequestV1, SetExpirationRequestV1,
+    TypingRequestV1
+};
+
+/// A direct conversation with another account, or a group conversation.
+///
+/// Direct conversations are compared and hashed by the address's normalized number, or its
+/// normalized UUID if it has no number. A number-only address is the same conversation as the
+/// peer's full address, however the number is formatted, but a UUID-only address only matches
+/// other addresses without a number.
+#[derive(Clone)]
+pub enum Conversation {
+    Direct(JsonAddressV1),
+    Group(String)
+}
+
+impl Conversation {
+    pub fn group<S: Into<String>>(id: S) -> Self {
+        Conversation::Group(id.into())
+    }
+
+    /// The conversation an incoming message belongs to: its group, or its sender if it wasn't
+    /// sent to a group
+    pub fn of(msg: &IncomingMessageV1) -> Option<Self> {
+        match msg.group_id() {
+            Some(group_id) => Some(Conversation::group(group_id)),
+            None => msg.source.clone().map(Conversation::Direct)
+        }
+    }
+
+    pub fn address(&self) -> Option<&JsonAddressV1> {
+        match self {
+            Conversation::Direct(address) => Some(address),
+            Conversation::Group(_) => None
+        }
+    }
+
+    pub fn group_id(&self) -> Option<&str> {
+        match self {
+            Conversation::Direct(_) => None,
+            Conversation::Group(id) => Some(id)
+        }
+    }
+
+    fn key(&self) -> (bool, String) {
+        match self {
+            Conversation::Direct(address) => (false, address_key(address)),
+            Conversation::Group(id) => (true, id.clone())
+        }
+    }
+}
+
+/// The normalized number of `address`, or its UUID if it has no number. Addresses that don't
+/// normalize are used as they are.
+pub(crate) fn address_key(address: &JsonAddressV1) -> String {
+    let (number, uuid) = address::normalize(address);
+    number.or(uuid)
+        .or_else(|| address.number.clone())
+        .or_else(|| address.uuid.clone())
+        .unwrap_or_default()
+}
+
+impl From<JsonAddressV1> for Conversation {
+    fn from(address: JsonAddressV1) -> Self {
+        Conversation::Direct(address)
+    }
+}
+
+impl PartialEq for Conversation {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Conversation {}
+
+impl Hash for Conversation {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl Display for Conversation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.key() {
+            (false, id) => write!(f, "direct:{}", id),
+            (true, id) => write!(f, "group:{}", id)
+        }
+    }
+}
+
+impl Debug for Conversation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Requests that can be sent to either a direct or a group conversation
+pub trait ConversationRequest {
+    /// Set the recipient fields for `conversation`, clearing the other ones
+    fn set_conversation(&mut self, conversation: &Conversation);
+
+    /// The conversation the request is currently addressed to
+    fn conversation(&self) -> Option<Conversation>;
+
+    /// Builder style version of [`set_conversation`](Self::set_conversation)
+    fn to(mut self, conversation: &Conversation) -> Self
+    where Self: Sized,
+    {
+        self.set_conversation(conversation);
+        self
+    }
+}
+
+macro_rules! conversation_request {
+    ($type:ty, $address:ident, $group:ident) => {
+        impl ConversationRequest for $type {
+            fn set_conversation(&mut self, conversation: &Conversation) {
+                self.$address = conversation.address().cloned();
+                self.$group = conversation.group_id().map(str::to_owned);
+            }
+
+            fn conversation(&self) -> Option<Conversation> {
+                match (&self.$address, &self.$group) {
+                    (_, Some(group)) => Some(Conversation::group(group.as_str())),
+                    (Some(address), None) => Some(Conversation::Direct(address.clone())),
+                    (None, None) => None
+                }
+            }
+        }
+    };
+}
+
+conversation_request!(SendRequestV1, recipient_address, recipient_group_id);
+conversation_request!(ReactRequestV1, recipient_address, recipient_group_id);
+conversation_request!(TypingRequestV1, address, group);
+conversation_request!(SetExpirationRequestV1, address, group);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::{Conversation, ConversationRequest};
+    use crate::types::{JsonAddressV1, SendRequestV1, TypingRequestV1};
+
+    fn address(number: &str) -> JsonAddressV1 {
+        JsonAddressV1 {
+            number: Some(number.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fills_request_fields() {
+        let direct = Conversation::from(address("+15550000001"));
+        let group = Conversation::group("group");
+
+        let send = SendRequestV1::default().to(&direct);
+        assert_eq!(send.recipient_address.as_ref().unwrap().number.as_deref(), Some("+15550000001"));
+        assert_eq!(send.conversation(), Some(direct));
+
+        let typing = TypingRequestV1 {
+            address: Some(address("+15550000001")),
+            ..Default::default()
+        }.to(&group);
+        assert!(typing.address.is_none());
+        assert_eq!(typing.group.as_deref(), Some("group"));
+    }
+
+    #[test]
+    fn hash_map_key() {
+        let mut counts = HashMap::new();
+        *counts.entry(Conversation::from(address("+15550000001"))).or_insert(0) += 1;
+        *counts.entry(Conversation::from(address("+15550000001"))).or_insert(0) += 1;
+        *counts.entry(Conversation::group("+15550000001")).or_insert(0) += 1;
+
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&Conversation::from(address("+15550000001"))], 2);
+    }
+
+    #[test]
+    fn normalized_key() {
+        let uuid = "6E8C2F1A-3B4D-4E5F-8A9B-0C1D2E3F4A5B";
+        let full = Conversation::from(JsonAddressV1 {
+            number: Some("+15550000001".to_owned()),
+            uuid: Some(uuid.to_owned()),
+            ..Default::default()
+        });
+        assert_eq!(full, Conversation::from(address("+1 555-000-0001")));
+        assert_eq!(full.to_string(), "direct:+15550000001");
+
+        let uuid_only = |uuid: &str| Conversation::from(JsonAddressV1 {
+            uuid: Some(uuid.to_owned()),
+            ..Default::default()
+        });
+        assert_eq!(uuid_only(uuid), uuid_only(&uuid.to_lowercase()));
+        assert_ne!(uuid_only(uuid), full);
+    }
+}
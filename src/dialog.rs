@@ -0,0 +1,309 @@
+//! Multi-step conversations with a single participant.
+//!
+//! [`Dialogs`] tracks dialogs by conversation and participant, so the same person can be in
+//! separate dialogs in a group and in a direct conversation. A handler asks a question and awaits
+//! the answer with [`Dialogs::next_message`], which only resolves with messages that participant
+//! sends in that conversation. For that to work, every incoming message has to be offered to
+//! [`Dialogs::handle`] first, e.g. with `Bot::dialogs`, and handlers must not block the loop
+//! receiving messages.
+//!
+//! Each dialog can also keep a state, which is saved to a file so a dialog can continue where it
+//! left off after a restart. Sending a cancel keyword ends the participant's dialog.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[cfg(feature = "async-std")]
+use async_std::channel::{bounded, Sender};
+#[cfg(feature = "smol")]
+use smol::channel::{bounded, Sender};
+
+use crate::conversation::{address_key, Conversation};
+use crate::types::{IncomingMessageV1, JsonAddressV1};
+use crate::SocketError;
+use crate::util::{load_json, save_json};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DialogError {
+    /// The participant didn't answer in time
+    Timeout,
+    /// The participant sent a cancel keyword, or another handler started waiting on them
+    Cancelled
+}
+
+impl Display for DialogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DialogError::Timeout => write!(f, "Timed out waiting for an answer"),
+            DialogError::Cancelled => write!(f, "Dialog was cancelled")
+        }
+    }
+}
+
+struct Inner<D> {
+    path: Option<PathBuf>,
+    states: HashMap<String, D>,
+    waiters: HashMap<String, Sender<IncomingMessageV1>>,
+    cancel_keywords: Vec<String>
+}
+
+impl<D: Serialize> Inner<D> {
+    fn save(&self) -> Result<(), SocketError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => { return Ok(()); }
+        };
+
+        save_json(path, &self.states)
+    }
+}
+
+/// Dialogs with a state of type `D`. Cheap to clone, so a clone can be kept in each handler.
+pub struct Dialogs<D> {
+    inner: Arc<Mutex<Inner<D>>>
+}
+
+impl<D> Clone for Dialogs<D> {
+    fn clone(&self) -> Self {
+        Dialogs {
+            inner: self.inner.clone()
+        }
+    }
+}
+
+impl<D> Default for Dialogs<D> {
+    fn default() -> Self {
+        Self::with_states(None, HashMap::new())
+    }
+}
+
+impl<D> Dialogs<D> {
+    fn with_states(path: Option<PathBuf>, states: HashMap<String, D>) -> Self {
+        Dialogs {
+            inner: Arc::new(Mutex::new(Inner {
+                path,
+                states,
+                waiters: HashMap::new(),
+                cancel_keywords: vec!["/cancel".to_owned()]
+            }))
+        }
+    }
+
+    /// Dialogs whose states are only kept in memory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Messages that cancel a dialog, compared case insensitively. Defaults to `/cancel`.
+    pub fn cancel_keywords<I, S>(self, keywords: I) -> Self
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str>,
+    {
+        self.inner.lock().unwrap().cancel_keywords = keywords.into_iter()
+            .map(|keyword| keyword.as_ref().to_lowercase())
+            .collect();
+        self
+    }
+}
+
+impl<D> Dialogs<D>
+where D: Clone + Serialize + DeserializeOwned,
+{
+    /// Dialogs whose states are persisted at `path`, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SocketError> {
+        let path = path.as_ref().to_path_buf();
+        let states = load_json(&path, "Failed to parse dialog file")?.unwrap_or_default();
+
+        Ok(Self::with_states(Some(path), states))
+    }
+
+    /// Offer an incoming message to the dialogs. Returns `true` if it was an answer someone was
+    /// waiting for, or cancelled a dialog, in which case it shouldn't be handled any further.
+    pub fn handle(&self, msg: &IncomingMessageV1) -> bool {
+        let key = match (msg.conversation(), &msg.source) {
+            (Some(conversation), Some(source)) => key(&conversation, source),
+            _ => { return false; }
+        };
+        let body = msg.data_message.as_ref().and_then(|data| data.body.as_deref());
+
+        let mut inner = self.inner.lock().unwrap();
+        let cancel = body.is_some_and(|body| inner.cancel_keywords.contains(&body.trim().to_lowercase()));
+
+        if cancel {
+            let waiter = inner.waiters.remove(&key);
+            let state = inner.states.remove(&key);
+            if state.is_some() {
+                if let Err(e) = inner.save() {
+                    warn!("Failed to save dialogs: {}", e);
+                }
+            }
+
+            // Dropping the waiter's sender wakes it with `Cancelled`
+            return waiter.is_some() || state.is_some();
+        }
+
+        if body.is_none() {
+            return false;
+        }
+
+        match inner.waiters.remove(&key) {
+            Some(waiter) => waiter.try_send(msg.clone()).is_ok(),
+            None => false
+        }
+    }
+
+    /// Wait for the next message `participant` sends in `conversation`
+    pub async fn next_message(&self, conversation: &Conversation, participant: &JsonAddressV1, timeout: Duration) -> Result<IncomingMessageV1, DialogError> {
+        let key = key(conversation, participant);
+        let (sender, receiver) = bounded(1);
+        // Replacing an earlier waiter cancels it
+        self.inner.lock().unwrap().waiters.insert(key.clone(), sender);
+
+        let received = {
+            let mut recv = std::pin::pin!(receiver.recv());
+            let mut sleep = std::pin::pin!(crate::sleep(timeout));
+            std::future::poll_fn(|cx| match recv.as_mut().poll(cx) {
+                Poll::Ready(received) => Poll::Ready(Some(received)),
+                Poll::Pending => sleep.as_mut().poll(cx).map(|()| None)
+            }).await
+        };
+
+        match received {
+            Some(Ok(msg)) => Ok(msg),
+            Some(Err(_)) => Err(DialogError::Cancelled),
+            None => {
+                // Closing our receiver tells our waiter apart from one that replaced it
+                drop(receiver);
+                let mut inner = self.inner.lock().unwrap();
+                if inner.waiters.get(&key).is_some_and(|waiter| waiter.is_closed()) {
+                    inner.waiters.remove(&key);
+                }
+                Err(DialogError::Timeout)
+            }
+        }
+    }
+
+    /// The state of the participant's dialog in `conversation`, if they are in one
+    pub fn state(&self, conversation: &Conversation, participant: &JsonAddressV1) -> Option<D> {
+        self.inner.lock().unwrap().states.get(&key(conversation, participant)).cloned()
+    }
+
+    /// Start or advance a dialog
+    pub fn set_state(&self, conversation: &Conversation, participant: &JsonAddressV1, state: D) -> Result<(), SocketError> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.states.insert(key(conversation, participant), state);
+        inner.save()
+    }
+
+    /// End a dialog, returning its last state
+    pub fn finish(&self, conversation: &Conversation, participant: &JsonAddressV1) -> Result<Option<D>, SocketError> {
+        let mut inner = self.inner.lock().unwrap();
+        let key = key(conversation, participant);
+        inner.waiters.remove(&key);
+        let state = inner.states.remove(&key);
+        inner.save()?;
+        Ok(state)
+    }
+}
+
+fn key(conversation: &Conversation, participant: &JsonAddressV1) -> String {
+    format!("{}/{}", conversation, address_key(participant))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::{DialogError, Dialogs};
+    use crate::conversation::Conversation;
+    use crate::test_support::{block_on, spawn, yield_now};
+    use crate::types::{IncomingMessageV1, JsonAddressV1, JsonDataMessageV1};
+    use crate::SocketError;
+
+    fn address(number: &str) -> JsonAddressV1 {
+        JsonAddressV1 {
+            number: Some(number.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn message(source: &str, body: &str) -> IncomingMessageV1 {
+        IncomingMessageV1 {
+            source: Some(address(source)),
+            data_message: Some(JsonDataMessageV1 {
+                body: Some(body.to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn answers_and_cancel() {
+        block_on(async {
+            let dialogs: Dialogs<u32> = Dialogs::new();
+            let alice = address("+15550000001");
+            let conversation = Conversation::from(alice.clone());
+
+            let waiting = dialogs.clone();
+            let (conv, participant) = (conversation.clone(), alice.clone());
+            let answer = spawn(async move {
+                waiting.next_message(&conv, &participant, Duration::from_secs(5)).await
+            });
+            while !dialogs.inner.lock().unwrap().waiters.contains_key("direct:+15550000001/+15550000001") {
+                yield_now().await;
+            }
+
+            // Only the participant's messages answer
+            assert!(!dialogs.handle(&message("+15550000002", "me")));
+            assert!(dialogs.handle(&message("+15550000001", "42")));
+            let answer = answer.await.unwrap();
+            assert_eq!(answer.data_message.unwrap().body.as_deref(), Some("42"));
+
+            // Nobody is waiting anymore, so messages go on to the bot
+            assert!(!dialogs.handle(&message("+15550000001", "43")));
+
+            dialogs.set_state(&conversation, &alice, 1).unwrap();
+            assert!(dialogs.handle(&message("+15550000001", " /CANCEL ")));
+            assert_eq!(dialogs.state(&conversation, &alice), None);
+
+            // The participant is the same however their address is written
+            dialogs.set_state(&conversation, &alice, 2).unwrap();
+            let full = JsonAddressV1 {
+                uuid: Some("6e8c2f1a-3b4d-4e5f-8a9b-0c1d2e3f4a5b".to_owned()),
+                ..address("+1 555 000 0001")
+            };
+            assert_eq!(dialogs.state(&Conversation::from(full.clone()), &full), Some(2));
+            dialogs.finish(&conversation, &alice).unwrap();
+
+            let result = dialogs.next_message(&conversation, &alice, Duration::from_millis(10)).await;
+            assert_eq!(result.err(), Some(DialogError::Timeout));
+            assert!(dialogs.inner.lock().unwrap().waiters.is_empty());
+        })
+    }
+
+    #[test]
+    fn persistent_state() -> Result<(), SocketError> {
+        let path = std::env::temp_dir().join(format!("signald-dialogs-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conversation = Conversation::group("group");
+        let alice = address("+15550000001");
+
+        Dialogs::open(&path)?.set_state(&conversation, &alice, "asked name".to_owned())?;
+
+        let dialogs: Dialogs<String> = Dialogs::open(&path)?;
+        assert_eq!(dialogs.state(&conversation, &alice).as_deref(), Some("asked name"));
+        assert_eq!(dialogs.state(&Conversation::from(alice.clone()), &alice), None);
+        dialogs.finish(&conversation, &alice)?;
+        assert!(Dialogs::<String>::open(&path)?.state(&conversation, &alice).is_none());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
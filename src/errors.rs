@@ -2,6 +2,8 @@ use serde::{Serialize, Deserialize};
 
 #[derive(Serialize, Deserialize)]
 pub struct ErrorInner {
+    /// Not every error has it, e.g. untrusted identity errors
+    #[serde(default)]
     pub more: String,
     pub message: String,
 }
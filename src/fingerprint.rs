@@ -0,0 +1,14 @@
+//! Displaying identity key fingerprints as the digits of a safety number.
+
+/// Five digits for each of the first six five-byte chunks of `fingerprint`, or `None` if it is
+/// shorter than 30 bytes
+pub(crate) fn digits(fingerprint: &[u8]) -> Option<String> {
+    let fingerprint = fingerprint.get(..30)?;
+
+    Some(fingerprint.chunks(5)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |value, byte| value << 8 | *byte as u64);
+            format!("{:05}", value % 100000)
+        })
+        .collect())
+}
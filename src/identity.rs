@@ -0,0 +1,682 @@
+//! Trusting contacts' new identity keys.
+//!
+//! When a contact reinstalls Signal their identity key changes, and sends to them fail until the
+//! new key is trusted. [`IdentityPolicy`] is an [`Interceptor`] that reacts to those failures
+//! according to a [`TrustMode`], and can retry the failed send once the key is trusted. Keys
+//! verified or unverified from the primary device are picked up from sync messages with
+//! [`IdentityPolicy::handle`].
+//!
+//! Every key change is recorded, and the record can be saved to a file. Changes the mode didn't
+//! trust automatically wait for [`IdentityPolicy::approve`]. Clones of a policy share its mode and
+//! record, so the copy installed on the client and the copy answering approvals see the same
+//! pending changes.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use sha2::{Digest, Sha512};
+use uuid::Uuid;
+
+use crate::actions::SocketWrapper;
+use crate::address::same_address;
+use crate::fingerprint::digits;
+use crate::interceptor::{Interceptor, Next};
+use crate::retry::{merge_results, resend_failed};
+use crate::socket::{AsyncSocket, MessageCommon};
+use crate::types::{GetIdentitiesRequestV1, IdentityKeyListV1, IdentityKeyV1, IncomingMessageV1, JsonAddressV1, TrustRequestV1};
+use crate::SocketError;
+use crate::util::{load_json, now, save_json};
+
+/// Hash iterations behind each half of a safety number
+const FINGERPRINT_ITERATIONS: usize = 5200;
+
+/// Which new identity keys to trust without asking
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrustMode {
+    /// Trust every new key, for unattended bots
+    Always,
+    /// Trust a contact's first key, but wait for approval when it changes
+    FirstUse,
+    /// Wait for approval of every new key
+    Manual
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TrustLevel {
+    TrustedUnverified,
+    TrustedVerified,
+    Untrusted
+}
+
+impl TrustLevel {
+    /// The trust level as sent to signald
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrustLevel::TrustedUnverified => "TRUSTED_UNVERIFIED",
+            TrustLevel::TrustedVerified => "TRUSTED_VERIFIED",
+            TrustLevel::Untrusted => "UNTRUSTED"
+        }
+    }
+
+    /// The trust level matching a verification state from a sync message
+    fn from_verified(verified: &str) -> Option<Self> {
+        match verified {
+            "VERIFIED" => Some(TrustLevel::TrustedVerified),
+            "DEFAULT" | "UNVERIFIED" => Some(TrustLevel::TrustedUnverified),
+            _ => None
+        }
+    }
+}
+
+/// How a key change was noticed
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeSource {
+    /// A send failed because of the new key
+    SendFailure,
+    /// The key was verified or unverified on another device
+    Verified
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct KeyChange {
+    pub account: Option<String>,
+    pub address: JsonAddressV1,
+    pub safety_number: String,
+    pub source: ChangeSource,
+    /// Milliseconds since the unix epoch
+    pub seen_at: u64,
+    /// The trust level the key was given, or `None` while it waits for approval
+    pub trust_level: Option<TrustLevel>
+}
+
+struct Inner {
+    mode: TrustMode,
+    retry: bool,
+    path: Option<PathBuf>,
+    changes: Vec<KeyChange>
+}
+
+impl Inner {
+    fn save(&self) -> Result<(), SocketError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => { return Ok(()); }
+        };
+
+        save_json(path, &self.changes)
+    }
+
+    fn find(&mut self, address: &JsonAddressV1, safety_number: &str) -> Option<&mut KeyChange> {
+        self.changes.iter_mut().find(|change| {
+            change.safety_number == safety_number && same_address(&change.address, address)
+        })
+    }
+
+    /// Record a key that failed a send, returning the trust level it should get now
+    fn decide(&mut self, account: Option<&str>, address: &JsonAddressV1, safety_number: &str, first_use: bool) -> Option<TrustLevel> {
+        if let Some(change) = self.find(address, safety_number) {
+            return change.trust_level;
+        }
+
+        let trust_level = match self.mode {
+            TrustMode::Always => Some(TrustLevel::TrustedUnverified),
+            TrustMode::FirstUse if first_use => Some(TrustLevel::TrustedUnverified),
+            _ => None
+        };
+
+        if trust_level.is_none() {
+            warn!("New identity key for {} waits for approval", address_label(address));
+        }
+
+        self.changes.push(KeyChange {
+            account: account.map(str::to_owned),
+            address: address.clone(),
+            safety_number: safety_number.to_owned(),
+            source: ChangeSource::SendFailure,
+            seen_at: now(),
+            trust_level
+        });
+        if let Err(e) = self.save() {
+            warn!("Failed to save identity changes: {:?}", e);
+        }
+
+        trust_level
+    }
+}
+
+#[derive(Clone)]
+pub struct IdentityPolicy {
+    inner: Arc<Mutex<Inner>>
+}
+
+impl IdentityPolicy {
+    /// A policy that keeps its record of key changes in memory
+    pub fn new(mode: TrustMode) -> Self {
+        Self::with_changes(mode, None, Vec::new())
+    }
+
+    /// A policy whose record of key changes is persisted at `path`, creating it if it doesn't
+    /// exist
+    pub fn open<P: AsRef<Path>>(mode: TrustMode, path: P) -> Result<Self, SocketError> {
+        let path = path.as_ref().to_path_buf();
+        let changes = load_json(&path, "Failed to parse identity changes file")?.unwrap_or_default();
+
+        Ok(Self::with_changes(mode, Some(path), changes))
+    }
+
+    fn with_changes(mode: TrustMode, path: Option<PathBuf>, changes: Vec<KeyChange>) -> Self {
+        IdentityPolicy {
+            inner: Arc::new(Mutex::new(Inner {
+                mode,
+                retry: false,
+                path,
+                changes
+            }))
+        }
+    }
+
+    /// Resend a message that failed because of a new key once the key is trusted. Off by
+    /// default.
+    pub fn retry_sends(self, retry: bool) -> Self {
+        self.inner.lock().unwrap().retry = retry;
+        self
+    }
+
+    /// Every key change seen, oldest first
+    pub fn changes(&self) -> Vec<KeyChange> {
+        self.inner.lock().unwrap().changes.clone()
+    }
+
+    /// Key changes waiting for approval
+    pub fn pending(&self) -> Vec<KeyChange> {
+        self.inner.lock().unwrap().changes.iter()
+            .filter(|change| change.trust_level.is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// Trust the pending keys of `address` with `trust_level`. Returns `false` if none were
+    /// pending.
+    pub async fn approve<T>(&self, socket: &mut SocketWrapper<T>, address: &JsonAddressV1, trust_level: TrustLevel) -> Result<bool, SocketError>
+    where T: AsyncSocket + Send,
+    {
+        let pending: Vec<_> = self.pending().into_iter()
+            .filter(|change| same_address(&change.address, address))
+            .collect();
+
+        for change in &pending {
+            socket.trust(trust_request(change.account.clone(), &change.address, &change.safety_number, trust_level), None).await?;
+            self.resolve(change, trust_level)?;
+        }
+
+        Ok(!pending.is_empty())
+    }
+
+    /// Apply a verification change made on another device. Returns `true` if `msg` was such a
+    /// sync message. If none of the contact's keys is the one that was verified, the newest
+    /// key waits for approval instead.
+    pub async fn handle<T>(&self, socket: &mut SocketWrapper<T>, msg: &IncomingMessageV1) -> Result<bool, SocketError>
+    where T: AsyncSocket + Send,
+    {
+        let verified = match msg.sync_message.as_ref().and_then(|sync| sync.verified.as_ref()) {
+            Some(verified) => verified,
+            None => { return Ok(false); }
+        };
+        let (address, trust_level) = match (&verified.destination, verified.verified.as_deref().and_then(TrustLevel::from_verified)) {
+            (Some(address), Some(trust_level)) => (address, trust_level),
+            _ => { return Ok(false); }
+        };
+
+        let identities = socket.get_identities(GetIdentitiesRequestV1 {
+            account: msg.account.clone(),
+            address: Some(address.clone())
+        }, None).await?;
+        let verified_key = verified.identity_key.as_deref()
+            .and_then(|key| identities.identities.iter().flatten().find(|identity| is_key(identity, address, key)));
+
+        let identity = match verified_key.or_else(|| newest(&identities)) {
+            Some(identity) => identity,
+            None => { return Ok(false); }
+        };
+        let safety_number = match identity.safety_number.clone() {
+            Some(safety_number) => safety_number,
+            None => { return Ok(false); }
+        };
+
+        if verified_key.is_none() {
+            warn!("Key verified for {} is not one of its known keys, waiting for approval", address_label(address));
+            let mut inner = self.inner.lock().unwrap();
+            if inner.find(address, &safety_number).is_none() {
+                inner.changes.push(KeyChange {
+                    account: msg.account.clone(),
+                    address: address.clone(),
+                    safety_number,
+                    source: ChangeSource::Verified,
+                    seen_at: now(),
+                    trust_level: None
+                });
+                inner.save()?;
+            }
+            return Ok(true);
+        }
+
+        socket.trust(trust_request(msg.account.clone(), address, &safety_number, trust_level), None).await?;
+        self.resolve(&KeyChange {
+            account: msg.account.clone(),
+            address: address.clone(),
+            safety_number,
+            source: ChangeSource::Verified,
+            seen_at: now(),
+            trust_level: None
+        }, trust_level)?;
+
+        Ok(true)
+    }
+
+    /// Record that `change` was trusted with `trust_level`
+    fn resolve(&self, change: &KeyChange, trust_level: TrustLevel) -> Result<(), SocketError> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.find(&change.address, &change.safety_number) {
+            Some(existing) => existing.trust_level = Some(trust_level),
+            None => inner.changes.push(KeyChange {
+                trust_level: Some(trust_level),
+                ..change.clone()
+            })
+        }
+        inner.save()
+    }
+
+    /// Apply the policy to a key that failed a send. Returns `true` if the key is now trusted.
+    async fn trust_new_key(&self, next: &mut Next<'_>, account: Option<&str>, address: JsonAddressV1, safety_number: Option<String>) -> Result<bool, SocketError> {
+        let response = next.run(request("get_identities", GetIdentitiesRequestV1 {
+            account: account.map(str::to_owned),
+            address: Some(address.clone())
+        })).await?;
+        if response.get("error").is_some() {
+            warn!("Failed to get identity keys of {}", address_label(&address));
+            return Ok(false);
+        }
+
+        let identities: IdentityKeyListV1 = serde_json::from_value(response).unwrap_or_default();
+        let safety_number = match safety_number.or_else(|| newest(&identities)?.safety_number.clone()) {
+            Some(safety_number) => safety_number,
+            None => { return Ok(false); }
+        };
+        let first_use = identities.identities.as_ref().is_none_or(|identities| identities.len() <= 1);
+
+        let trust_level = self.inner.lock().unwrap().decide(account, &address, &safety_number, first_use);
+        let trust_level = match trust_level {
+            Some(trust_level) => trust_level,
+            None => { return Ok(false); }
+        };
+
+        debug!("Trusting new identity key for {}", address_label(&address));
+        let response = next.run(request("trust", trust_request(account.map(str::to_owned), &address, &safety_number, trust_level))).await?;
+        if response.get("error").is_some() {
+            warn!("Failed to trust identity key of {}", address_label(&address));
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl Interceptor for IdentityPolicy {
+    async fn intercept(&self, mut request: Value, mut next: Next<'_>) -> Result<Value, SocketError> {
+        let mut response = next.run(request.clone()).await?;
+
+        if request.get("type").and_then(Value::as_str) != Some("send") {
+            return Ok(response);
+        }
+
+        // `send` names the account `username`
+        let account = request.get("username").and_then(Value::as_str).map(str::to_owned);
+        let retry = self.inner.lock().unwrap().retry;
+
+        // A direct send fails as a whole
+        if is_untrusted_identity(&response) {
+            let error = &response["error"];
+            let address = match error.get("identifier").and_then(Value::as_str) {
+                Some(identifier) => address(identifier),
+                None => { return Ok(response); }
+            };
+            let safety_number = error["identity_key"].get("safety_number").and_then(Value::as_str).map(str::to_owned);
+
+            if self.trust_new_key(&mut next, account.as_deref(), address, safety_number).await? && retry {
+                response = next.run(request).await?;
+            }
+            return Ok(response);
+        }
+
+        // Group sends report the failure per recipient
+        let failed: Vec<JsonAddressV1> = response.get("results")
+            .and_then(Value::as_array)
+            .map(|results| results.iter()
+                .filter(|result| is_identity_failure(result))
+                .filter_map(|result| serde_json::from_value(result["address"].clone()).ok())
+                .collect())
+            .unwrap_or_default();
+
+        let mut trusted = false;
+        for address in failed {
+            trusted |= self.trust_new_key(&mut next, account.as_deref(), address, None).await?;
+        }
+
+        if trusted && retry {
+            resend_failed(&mut request, &response, is_identity_failure);
+            let retry = next.run(request).await?;
+            if retry.get("error").is_none() {
+                merge_results(&mut response, retry, is_identity_failure);
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+fn is_untrusted_identity(response: &Value) -> bool {
+    response.get("error").is_some() && response.get("error_type")
+        .and_then(Value::as_str)
+        .is_some_and(|error_type| error_type.starts_with("UntrustedIdentity"))
+}
+
+fn is_identity_failure(result: &Value) -> bool {
+    result.get("identityFailure").is_some_and(|failure| !failure.is_null())
+}
+
+/// The most recently added identity key
+fn newest(identities: &IdentityKeyListV1) -> Option<&IdentityKeyV1> {
+    identities.identities.as_ref()?.iter().max_by_key(|identity| identity.added)
+}
+
+/// Whether `identity` is `key`, a base64 encoded identity key of `address` from a sync message.
+/// signald only shows keys as safety numbers, so this checks whether one half of the safety
+/// number is the key's fingerprint for the contact's number or UUID.
+fn is_key(identity: &IdentityKeyV1, address: &JsonAddressV1, key: &str) -> bool {
+    let key = match STANDARD.decode(key.trim()) {
+        Ok(key) => key,
+        Err(_) => { return false; }
+    };
+    let safety_number = match identity.safety_number.as_deref() {
+        Some(safety_number) if safety_number.len() == 60 && safety_number.is_ascii() => safety_number,
+        _ => { return false; }
+    };
+
+    let number = address.number.as_ref().map(|number| number.as_bytes().to_vec());
+    let uuid = address.uuid.as_deref()
+        .and_then(|uuid| Uuid::parse_str(uuid).ok())
+        .map(|uuid| uuid.as_bytes().to_vec());
+
+    number.into_iter().chain(uuid).any(|identifier| {
+        let fingerprint = fingerprint(&key, &identifier);
+        safety_number[..30] == fingerprint || safety_number[30..] == fingerprint
+    })
+}
+
+/// The 30 digits `key` shows as in the safety number of the contact identified by `identifier`
+fn fingerprint(key: &[u8], identifier: &[u8]) -> String {
+    let mut hash = Sha512::new()
+        .chain_update([0, 0])
+        .chain_update(key)
+        .chain_update(identifier)
+        .chain_update(key)
+        .finalize();
+    for _ in 1..FINGERPRINT_ITERATIONS {
+        hash = Sha512::new().chain_update(hash).chain_update(key).finalize();
+    }
+
+    // A SHA-512 hash always has enough bytes
+    digits(&hash).unwrap_or_default()
+}
+
+fn trust_request(account: Option<String>, address: &JsonAddressV1, safety_number: &str, trust_level: TrustLevel) -> TrustRequestV1 {
+    TrustRequestV1 {
+        account,
+        address: Some(address.clone()),
+        safety_number: Some(safety_number.to_owned()),
+        trust_level: Some(trust_level.as_str().to_owned()),
+        ..Default::default()
+    }
+}
+
+/// A request to run through the rest of the interceptor chain
+fn request<M: Serialize>(type_: &str, msg: M) -> Value {
+    serde_json::to_value(MessageCommon::new(
+        Uuid::new_v4().to_simple().to_string(),
+        type_.to_owned(),
+        "v1".to_owned(),
+        msg
+    )).unwrap()
+}
+
+/// The address an untrusted identity error names, which is either a UUID or a number
+fn address(identifier: &str) -> JsonAddressV1 {
+    match Uuid::parse_str(identifier) {
+        Ok(_) => JsonAddressV1 {
+            uuid: Some(identifier.to_owned()),
+            ..Default::default()
+        },
+        Err(_) => JsonAddressV1 {
+            number: Some(identifier.to_owned()),
+            ..Default::default()
+        }
+    }
+}
+
+/// The UUID or number of `address`, for logging
+fn address_label(address: &JsonAddressV1) -> &str {
+    address.uuid.as_deref().or(address.number.as_deref()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use serde_json::{json, Value};
+    use super::{ChangeSource, IdentityPolicy, TrustLevel, TrustMode};
+    use crate::actions::SocketWrapper;
+    use crate::test_support::{block_on, ScriptedSocket};
+    use crate::types::{IncomingMessageV1, JsonAddressV1, JsonSyncMessageV1, JsonVerifiedMessageV1, SendRequestV1};
+    use crate::SocketError;
+
+    fn socket(policy: &IdentityPolicy, responses: Vec<Value>) -> SocketWrapper<ScriptedSocket> {
+        SocketWrapper::new(ScriptedSocket::new(responses))
+            .with_interceptor(policy.clone())
+    }
+
+    fn address(number: &str) -> JsonAddressV1 {
+        JsonAddressV1 {
+            number: Some(number.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn send_request() -> SendRequestV1 {
+        SendRequestV1 {
+            username: Some("+15551234567".to_owned()),
+            recipient_address: Some(address("+15550000001")),
+            message_body: Some("hello".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn untrusted() -> Value {
+        json!({
+            "id": "",
+            "type": "send",
+            "error": {
+                "identifier": "+15550000001",
+                "identity_key": { "safety_number": "22222", "trust_level": "UNTRUSTED" },
+                "message": "untrusted identity"
+            },
+            "error_type": "UntrustedIdentityError"
+        })
+    }
+
+    fn identities(count: usize) -> Value {
+        let identities: Vec<_> = (0..count)
+            .map(|i| json!({ "added": i, "safety_number": format!("{}", 11111 * (i + 1)) }))
+            .collect();
+        json!({ "address": { "number": "+15550000001" }, "identities": identities })
+    }
+
+    #[test]
+    fn always_trusts_and_retries() -> Result<(), SocketError> {
+        block_on(async {
+            let policy = IdentityPolicy::new(TrustMode::Always).retry_sends(true);
+            let mut socket = socket(&policy, vec![untrusted(), identities(2), json!({}), json!({ "timestamp": 5, "results": [] })]);
+
+            let response = socket.send(send_request(), None).await?;
+            assert_eq!(response.timestamp, Some(5));
+
+            let sent = &socket.socket.sent;
+            assert_eq!(sent[1]["type"], "get_identities");
+            assert_eq!(sent[2]["type"], "trust");
+            assert_eq!(sent[2]["safety_number"], "22222");
+            assert_eq!(sent[2]["trust_level"], "TRUSTED_UNVERIFIED");
+            assert_eq!(sent[2]["account"], "+15551234567");
+            assert_eq!(sent[3]["type"], "send");
+
+            let changes = policy.changes();
+            assert_eq!(changes.len(), 1);
+            assert_eq!(changes[0].trust_level, Some(TrustLevel::TrustedUnverified));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn group_retry_goes_to_failed_members() -> Result<(), SocketError> {
+        block_on(async {
+            let group_failure = json!({
+                "timestamp": 9,
+                "results": [
+                    { "address": { "number": "+15550000001" }, "identityFailure": "BVeLJoJ" },
+                    { "address": { "number": "+15550000002" }, "success": {} }
+                ]
+            });
+            let retried = json!({ "timestamp": 9, "results": [{ "address": { "number": "+15550000001" }, "success": {} }] });
+            let policy = IdentityPolicy::new(TrustMode::Always).retry_sends(true);
+            let mut socket = socket(&policy, vec![group_failure, identities(2), json!({}), retried]);
+
+            let response = socket.send(SendRequestV1 {
+                recipient_address: None,
+                recipient_group_id: Some("group".to_owned()),
+                ..send_request()
+            }, None).await?;
+            assert!(response.results.unwrap().iter().all(|result| result.identity_failure.is_none()));
+
+            let resend = &socket.socket.sent[3];
+            assert_eq!(resend["type"], "send");
+            assert_eq!(resend["timestamp"], 9);
+            assert_eq!(resend["members"], json!([{ "number": "+15550000001" }]));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn first_use_waits_for_approval_of_changes() -> Result<(), SocketError> {
+        block_on(async {
+            let group_failure = json!({
+                "timestamp": 9,
+                "results": [{ "address": { "number": "+15550000001" }, "identityFailure": "BVeLJoJ" }]
+            });
+            let policy = IdentityPolicy::new(TrustMode::FirstUse);
+            let mut socket = socket(&policy, vec![group_failure, identities(2), json!({})]);
+
+            let response = socket.send(send_request(), None).await?;
+            assert!(response.results.unwrap()[0].identity_failure.is_some());
+            // The key changed, so nothing is trusted or resent
+            assert_eq!(socket.socket.sent.len(), 2);
+
+            let pending = policy.pending();
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].safety_number, "22222");
+            assert_eq!(pending[0].source, ChangeSource::SendFailure);
+
+            assert!(policy.approve(&mut socket, &address("+15550000001"), TrustLevel::TrustedVerified).await?);
+            assert_eq!(socket.socket.sent[2]["trust_level"], "TRUSTED_VERIFIED");
+            assert!(policy.pending().is_empty());
+            assert!(!policy.approve(&mut socket, &address("+15550000001"), TrustLevel::TrustedVerified).await?);
+            Ok(())
+        })
+    }
+
+    /// A sync message verifying `key`, and the contact's keys: the verified one added first
+    /// if `known`, then a newer one
+    fn verified(key: &[u8], known: bool) -> (IncomingMessageV1, Value) {
+        let msg = IncomingMessageV1 {
+            account: Some("+15551234567".to_owned()),
+            sync_message: Some(JsonSyncMessageV1 {
+                verified: Some(JsonVerifiedMessageV1 {
+                    destination: Some(address("+15550000001")),
+                    identity_key: Some(STANDARD.encode(key)),
+                    verified: Some("VERIFIED".to_owned()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let verified_number = format!("{}{}", "0".repeat(30), super::fingerprint(key, b"+15550000001"));
+        let mut identities = vec![json!({ "added": 2, "safety_number": "1".repeat(60) })];
+        if known {
+            identities.push(json!({ "added": 1, "safety_number": verified_number }));
+        }
+        (msg, json!({ "address": { "number": "+15550000001" }, "identities": identities }))
+    }
+
+    #[test]
+    fn verified_sync_message() -> Result<(), SocketError> {
+        block_on(async {
+            let path = std::env::temp_dir().join(format!("signald-identity-{}.json", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+
+            let key = [5u8; 33];
+            let (msg, identities) = verified(&key, true);
+            let policy = IdentityPolicy::open(TrustMode::Manual, &path)?;
+            let mut socket = socket(&policy, vec![identities, json!({})]);
+
+            assert!(policy.handle(&mut socket, &msg).await?);
+            assert!(!policy.handle(&mut socket, &IncomingMessageV1::default()).await?);
+            // The verified key is trusted, not the newer one
+            let safety_number = socket.socket.sent[1]["safety_number"].as_str().unwrap();
+            assert!(safety_number.starts_with(&"0".repeat(30)));
+            assert_eq!(socket.socket.sent[1]["trust_level"], "TRUSTED_VERIFIED");
+
+            let changes = IdentityPolicy::open(TrustMode::Manual, &path)?.changes();
+            assert_eq!(changes.len(), 1);
+            assert_eq!(changes[0].source, ChangeSource::Verified);
+            assert_eq!(changes[0].trust_level, Some(TrustLevel::TrustedVerified));
+
+            std::fs::remove_file(&path)?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn verified_unknown_key_waits_for_approval() -> Result<(), SocketError> {
+        block_on(async {
+            let (msg, identities) = verified(&[5u8; 33], false);
+            let policy = IdentityPolicy::new(TrustMode::Always);
+            let mut socket = socket(&policy, vec![identities]);
+
+            assert!(policy.handle(&mut socket, &msg).await?);
+            // Nothing is trusted
+            assert_eq!(socket.socket.sent.len(), 1);
+
+            let pending = policy.pending();
+            assert_eq!(pending.len(), 1);
+            assert_eq!(pending[0].safety_number, "1".repeat(60));
+            assert_eq!(pending[0].source, ChangeSource::Verified);
+            Ok(())
+        })
+    }
+}
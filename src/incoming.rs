@@ -0,0 +1,178 @@
+//! Helpers for responding to incoming messages.
+//!
+//! These build the request for replying, quoting, reacting or sending a read receipt, sent to
+//! the group the message came from, or directly to the sender if it wasn't sent to a group.
+//! They return `None` for messages that can't be responded to, like receipts or messages
+//! without a sender.
+
+#[cfg(any(feature = "bot", feature = "rules"))]
+use crate::address::{address_of, same_address};
+use crate::conversation::{Conversation, ConversationRequest};
+use crate::types::{
+    IncomingMessageV1, JsonQuoteV1, JsonReactionV1, MarkReadRequestV1, ReactRequestV1,
+    SendRequestV1
+};
+
+impl IncomingMessageV1 {
+    /// The id of the group this message was sent to, if any
+    pub fn group_id(&self) -> Option<&str> {
+        let data_message = self.data_message.as_ref()?;
+
+        data_message.group_v_2.as_ref().and_then(|group| group.id.as_deref())
+            .or_else(|| data_message.group.as_ref().and_then(|group| group.group_id.as_deref()))
+    }
+
+    /// The conversation this message belongs to, see [`Conversation::of`]
+    pub fn conversation(&self) -> Option<Conversation> {
+        Conversation::of(self)
+    }
+
+    /// The sent timestamp identifying this message for quotes, reactions and receipts
+    pub fn sent_timestamp(&self) -> Option<i64> {
+        self.data_message.as_ref()
+            .and_then(|data_message| data_message.timestamp)
+            .or(self.timestamp)
+    }
+
+    /// Whether this is a sync message from one of our own devices, or was sent by our own
+    /// account. The account may be a number or a UUID.
+    #[cfg(any(feature = "bot", feature = "rules"))]
+    pub(crate) fn is_own(&self) -> bool {
+        if self.sync_message.is_some() {
+            return true;
+        }
+
+        match (self.account.as_deref(), self.source.as_ref()) {
+            (Some(account), Some(source)) => same_address(source, &address_of(account)),
+            _ => false
+        }
+    }
+
+    /// A message replying to this one in the same conversation
+    pub fn reply<S: Into<String>>(&self, text: S) -> Option<SendRequestV1> {
+        let conversation = self.conversation()?;
+
+        Some(SendRequestV1 {
+            username: self.account.clone(),
+            message_body: Some(text.into()),
+            ..Default::default()
+        }.to(&conversation))
+    }
+
+    /// A reply that quotes this message
+    pub fn quote_reply<S: Into<String>>(&self, text: S) -> Option<SendRequestV1> {
+        let data_message = self.data_message.as_ref()?;
+        let mut reply = self.reply(text)?;
+
+        reply.quote = Some(JsonQuoteV1 {
+            id: Some(self.sent_timestamp()?),
+            author: self.source.clone(),
+            text: data_message.body.clone(),
+            mentions: data_message.mentions.clone(),
+            ..Default::default()
+        });
+
+        Some(reply)
+    }
+
+    /// React to this message with `emoji`
+    pub fn react<S: Into<String>>(&self, emoji: S) -> Option<ReactRequestV1> {
+        self.data_message.as_ref()?;
+        let conversation = self.conversation()?;
+
+        Some(ReactRequestV1 {
+            username: self.account.clone(),
+            reaction: Some(JsonReactionV1 {
+                emoji: Some(emoji.into()),
+                remove: Some(false),
+                target_author: self.source.clone(),
+                target_sent_timestamp: Some(self.sent_timestamp()?)
+            }),
+            ..Default::default()
+        }.to(&conversation))
+    }
+
+    /// A read receipt for this message. Receipts always go to the sender, even for group
+    /// messages.
+    pub fn mark_read(&self) -> Option<MarkReadRequestV1> {
+        Some(MarkReadRequestV1 {
+            account: self.account.clone(),
+            to: Some(self.source.clone()?),
+            timestamps: Some(vec![self.sent_timestamp()?]),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{IncomingMessageV1, JsonAddressV1, JsonDataMessageV1, JsonGroupV2InfoV1};
+
+    fn message(group: Option<&str>) -> IncomingMessageV1 {
+        IncomingMessageV1 {
+            account: Some("+15550000000".to_owned()),
+            source: Some(JsonAddressV1 {
+                number: Some("+15550000001".to_owned()),
+                ..Default::default()
+            }),
+            timestamp: Some(100),
+            data_message: Some(JsonDataMessageV1 {
+                body: Some("ping".to_owned()),
+                timestamp: Some(100),
+                group_v_2: group.map(|id| JsonGroupV2InfoV1 {
+                    id: Some(id.to_owned()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn direct_reply() {
+        let reply = message(None).quote_reply("pong").unwrap();
+        assert_eq!(reply.username.as_deref(), Some("+15550000000"));
+        assert_eq!(reply.recipient_address.unwrap().number.as_deref(), Some("+15550000001"));
+        assert!(reply.recipient_group_id.is_none());
+
+        let quote = reply.quote.unwrap();
+        assert_eq!(quote.id, Some(100));
+        assert_eq!(quote.text.as_deref(), Some("ping"));
+    }
+
+    #[test]
+    fn group_react_and_read() {
+        let msg = message(Some("group"));
+
+        let react = msg.react("👍").unwrap();
+        assert!(react.recipient_address.is_none());
+        assert_eq!(react.recipient_group_id.as_deref(), Some("group"));
+        assert_eq!(react.reaction.unwrap().target_sent_timestamp, Some(100));
+
+        let read = msg.mark_read().unwrap();
+        assert_eq!(read.to.unwrap().number.as_deref(), Some("+15550000001"));
+        assert_eq!(read.timestamps, Some(vec![100]));
+    }
+
+    #[cfg(any(feature = "bot", feature = "rules"))]
+    #[test]
+    fn own_messages() {
+        let uuid = "9c2a1f8e-4b3d-4e5f-8a6b-7c8d9e0f1a2b";
+        let mut msg = message(None);
+        assert!(!msg.is_own());
+
+        msg.source = Some(JsonAddressV1 {
+            number: Some("+1 555 000 0000".to_owned()),
+            ..Default::default()
+        });
+        assert!(msg.is_own());
+
+        msg.account = Some(uuid.to_uppercase());
+        msg.source = Some(JsonAddressV1 {
+            uuid: Some(uuid.to_owned()),
+            ..Default::default()
+        });
+        assert!(msg.is_own());
+    }
+}
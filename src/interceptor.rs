@@ -0,0 +1,214 @@
+//! Interceptors wrap every request made through a [`SocketWrapper`], so cross-cutting behavior
+//! like retries, logging, injecting the account or dry runs can be written once instead of
+//! around each generated action.
+//!
+//! Interceptors see the outgoing request as the JSON [`Value`] that will be written to the
+//! socket, and the raw response `Value` before it is parsed. They run in the order they were
+//! added, and each one decides whether and how often to pass the request on with
+//! [`Next::run`].
+
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::actions::SocketWrapper;
+use crate::socket::{AsyncSocket, MessageCommon};
+use crate::SocketError;
+
+#[async_trait]
+pub trait Interceptor: Send + Sync {
+    /// Handle `request`, usually by passing it (possibly modified) on to `next` and returning
+    /// the response. Not calling `next` short-circuits the request without sending it.
+    async fn intercept(&self, request: Value, next: Next<'_>) -> Result<Value, SocketError>;
+}
+
+/// The rest of the interceptor chain, ending with the socket itself
+pub struct Next<'a> {
+    socket: &'a mut (dyn AsyncSocket + Send),
+    interceptors: &'a [Arc<dyn Interceptor>]
+}
+
+impl Next<'_> {
+    /// Pass `request` to the next interceptor, or send it if this is the end of the chain.
+    /// May be called more than once, e.g. to retry a request.
+    pub async fn run(&mut self, request: Value) -> Result<Value, SocketError> {
+        match self.interceptors.split_first() {
+            Some((interceptor, rest)) => {
+                let next = Next {
+                    socket: &mut *self.socket,
+                    interceptors: rest
+                };
+                interceptor.intercept(request, next).await
+            },
+            None => {
+                let id = request.get("id")
+                    .and_then(|id| id.as_str())
+                    .and_then(|id| Uuid::parse_str(id).ok())
+                    .ok_or(SocketError::General("Request has no valid id"))?;
+
+                let mut buf = serde_json::to_vec(&request).unwrap();
+                buf.push(b'\n');
+
+                send(self.socket, &buf, id).await
+            }
+        }
+    }
+}
+
+async fn send(socket: &mut (dyn AsyncSocket + Send), buf: &[u8], id: Uuid) -> Result<Value, SocketError> {
+    socket.write(buf, &id).await?;
+    socket.get_response(id).await
+}
+
+impl<T> SocketWrapper<T>
+where T: AsyncSocket + Send,
+{
+    /// Wrap `socket` with no interceptors
+    pub fn new(socket: T) -> Self {
+        SocketWrapper {
+            socket,
+            interceptors: Vec::new()
+        }
+    }
+
+    /// Add an interceptor to the end of the chain
+    pub fn add_interceptor<I: Interceptor + 'static>(&mut self, interceptor: I) {
+        self.interceptors.push(Arc::new(interceptor));
+    }
+
+    /// Builder style version of [`add_interceptor`](Self::add_interceptor)
+    pub fn with_interceptor<I: Interceptor + 'static>(mut self, interceptor: I) -> Self {
+        self.add_interceptor(interceptor);
+        self
+    }
+
+    pub(crate) async fn call<M: Serialize>(&mut self, msg: &MessageCommon<M>, id: &Uuid) -> Result<Value, SocketError> {
+        if self.interceptors.is_empty() {
+            let mut buf = serde_json::to_vec(msg).unwrap();
+            buf.push(b'\n');
+
+            return send(&mut self.socket, &buf, *id).await;
+        }
+
+        let request = serde_json::to_value(msg).unwrap();
+        let mut next = Next {
+            socket: &mut self.socket,
+            interceptors: &self.interceptors
+        };
+
+        next.run(request).await
+    }
+}
+
+#[cfg(all(test, any(feature = "async-std", feature = "tokio", feature = "smol")))]
+mod tests {
+    use async_trait::async_trait;
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+    use super::{Interceptor, Next};
+    use crate::actions::SocketWrapper;
+    use crate::socket::AsyncSocket;
+    use crate::test_support::block_on;
+    use crate::types::{JsonAddressV1, SendRequestV1};
+    use crate::SocketError;
+
+    /// Echoes each request back as the response's data, failing the first `failures` requests
+    #[derive(Default)]
+    struct EchoSocket {
+        last: Value,
+        failures: usize
+    }
+
+    #[async_trait]
+    impl AsyncSocket for EchoSocket {
+        async fn write<'a>(&'a mut self, buf: &'a [u8], _id: &Uuid) -> Result<(), SocketError> {
+            self.last = serde_json::from_slice(buf).unwrap();
+            Ok(())
+        }
+
+        async fn get_response<'a>(&'a mut self, _id: Uuid) -> Result<Value, SocketError> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(SocketError::General("Connection reset"));
+            }
+
+            Ok(json!({ "timestamp": self.last["timestamp"], "results": [] }))
+        }
+    }
+
+    struct InjectAccount;
+
+    #[async_trait]
+    impl Interceptor for InjectAccount {
+        async fn intercept(&self, mut request: Value, mut next: Next<'_>) -> Result<Value, SocketError> {
+            request["account"] = json!("+15551234567");
+            next.run(request).await
+        }
+    }
+
+    struct Retry;
+
+    #[async_trait]
+    impl Interceptor for Retry {
+        async fn intercept(&self, request: Value, mut next: Next<'_>) -> Result<Value, SocketError> {
+            loop {
+                if let Ok(response) = next.run(request.clone()).await {
+                    return Ok(response);
+                }
+            }
+        }
+    }
+
+    struct DryRun;
+
+    #[async_trait]
+    impl Interceptor for DryRun {
+        async fn intercept(&self, _request: Value, _next: Next<'_>) -> Result<Value, SocketError> {
+            Ok(json!({ "timestamp": 0 }))
+        }
+    }
+
+    fn request() -> SendRequestV1 {
+        SendRequestV1 {
+            recipient_address: Some(JsonAddressV1 {
+                number: Some("+15557654321".to_owned()),
+                ..Default::default()
+            }),
+            message_body: Some("hello".to_owned()),
+            timestamp: Some(42),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chain() -> Result<(), SocketError> {
+        block_on(async {
+            let mut socket = SocketWrapper::new(EchoSocket { failures: 2, ..Default::default() })
+                .with_interceptor(Retry)
+                .with_interceptor(InjectAccount);
+
+            let response = socket.send(request(), None).await?;
+            assert_eq!(response.timestamp, Some(42));
+            assert_eq!(socket.socket.last["account"], "+15551234567");
+            assert_eq!(socket.socket.last["type"], "send");
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn short_circuit() -> Result<(), SocketError> {
+        block_on(async {
+            let mut socket = SocketWrapper::new(EchoSocket::default())
+                .with_interceptor(DryRun);
+
+            let response = socket.send(request(), None).await?;
+            assert_eq!(response.timestamp, Some(0));
+            assert_eq!(socket.socket.last, Value::Null);
+
+            Ok(())
+        })
+    }
+}
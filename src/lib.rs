@@ -1,22 +1,86 @@
+#[macro_use]
+mod trace;
+
 pub mod types;
 pub mod actions;
 pub mod socket;
 pub mod errors;
+pub mod address;
+#[cfg(feature = "attachments")]
+pub mod attachments;
+#[cfg(all(feature = "bot", any(feature = "async-std", feature = "smol")))]
+pub mod bot;
+pub mod command;
+pub mod conversation;
+#[cfg(any(feature = "async-std", feature = "smol"))]
+pub mod dialog;
+mod incoming;
+pub mod interceptor;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol", feature = "verify"))]
+mod fingerprint;
+#[cfg(feature = "link")]
+pub mod link;
+pub mod mentions;
+pub mod metrics;
+#[cfg(any(feature = "link", feature = "verify"))]
+pub mod qr;
+pub mod receipts;
+#[cfg(feature = "rules")]
+pub mod rules;
+#[cfg(feature = "verify")]
+pub mod safety_number;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+pub mod retry;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+pub mod outbox;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+pub mod identity;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+pub mod audit;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+pub mod contacts;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+pub mod received_attachments;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+pub mod registration;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+pub mod typing;
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+mod util;
+#[cfg(all(test, any(feature = "async-std", feature = "tokio", feature = "smol")))]
+mod test_support;
 
 #[cfg(feature = "async-std")]
 pub mod async_std_socket;
 #[cfg(feature = "async-std")]
 pub use crate::async_std_socket::{SocketError, Signald};
+#[cfg(feature = "async-std")]
+pub(crate) use crate::async_std_socket::sleep;
 
 #[cfg(feature = "tokio")]
 pub mod tokio_socket;
 #[cfg(feature = "tokio")]
 pub use crate::tokio_socket::{SocketError, Signald};
 #[cfg(feature = "tokio")]
-use tokio::test;
+pub(crate) use crate::tokio_socket::sleep;
+
+#[cfg(feature = "smol")]
+pub mod smol_socket;
+#[cfg(feature = "smol")]
+pub use crate::smol_socket::{SocketError, Signald};
+#[cfg(feature = "smol")]
+pub(crate) use crate::smol_socket::sleep;
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "blocking")]
+mod blocking_actions;
+#[cfg(all(feature = "blocking", not(any(feature = "async-std", feature = "tokio", feature = "smol"))))]
+pub use crate::blocking::SocketError;
 
 #[cfg(test)]
 mod tests {
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
     use super::SocketError;
 
     #[cfg(feature = "tokio")]
@@ -31,16 +95,24 @@ mod tests {
         register().await
     }
 
+    #[cfg(feature = "smol")]
+    #[test]
+    fn smol_register() -> Result<(), SocketError> {
+        smol::block_on(register())
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
     async fn register() -> Result<(), SocketError>  {
         use super::types::RegisterRequestV1;
-        use super::Signald;
 
-        let mut socket = Signald::connect("run/signald.sock", |_| {}).await?;
+        let mut socket = crate::test_support::connect("run/signald.sock".as_ref()).await?;
 
-        let mut register = RegisterRequestV1::default();
-        register.account = Some("+15551234567".to_owned());
+        let register = RegisterRequestV1 {
+            account: Some("+15551234567".to_owned()),
+            ..Default::default()
+        };
 
-        let response = socket.register(register).await;
+        let response = socket.register(register, None).await;
 
         match response {
             Ok(response) => assert_eq!(response.address.unwrap().number.unwrap().as_str(), "+15551234567"),
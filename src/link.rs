@@ -0,0 +1,152 @@
+//! Linking signald as a secondary device.
+//!
+//! Linking takes three steps: `generate_linking_uri` returns a URI for the primary device to
+//! scan as a QR code, then `finish_link` waits for the scan and creates the account.
+//! `link_device` drives the whole flow, showing the QR code in the terminal or writing it to a
+//! PNG file along the way.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::qr::Qr;
+use crate::types::{FinishLinkRequestV1, GenerateLinkingURIRequestV1};
+
+/// Where to show the QR code while linking
+pub enum QrDisplay {
+    /// Print it to stdout with Unicode block characters, which needs a dark terminal background
+    Terminal,
+    /// Write it to a PNG file
+    Png(PathBuf)
+}
+
+/// A linking URI encoded as a QR code
+pub struct LinkingQr {
+    pub uri: String,
+    qr: Qr
+}
+
+impl LinkingQr {
+    /// Returns `None` if the URI is too long to encode
+    pub fn new<S: Into<String>>(uri: S) -> Option<Self> {
+        let uri = uri.into();
+        let qr = Qr::new(&uri)?;
+        Some(LinkingQr { uri, qr })
+    }
+
+    /// Render the code with half block characters, see [`Qr::to_terminal_string`]
+    pub fn to_terminal_string(&self) -> String {
+        self.qr.to_terminal_string()
+    }
+
+    /// Write the code to a PNG file, with each module `scale` pixels wide
+    pub fn save_png<P: AsRef<Path>>(&self, path: P, scale: u32) -> Result<(), std::io::Error> {
+        self.qr.save_png(path, scale)
+    }
+
+    fn show(&self, display: &QrDisplay) -> Result<(), std::io::Error> {
+        match display {
+            QrDisplay::Terminal => {
+                let mut stdout = std::io::stdout();
+                write!(stdout, "Scan this code from Signal on your phone, under Settings > Linked devices:\n\n{}\n", self.to_terminal_string())?;
+                stdout.flush()
+            },
+            QrDisplay::Png(path) => self.save_png(path, 8)
+        }
+    }
+}
+
+fn finish_request(session_id: Option<String>, device_name: &str) -> FinishLinkRequestV1 {
+    FinishLinkRequestV1 {
+        device_name: Some(device_name.to_owned()),
+        session_id
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+impl<T> crate::actions::SocketWrapper<T>
+where T: crate::socket::AsyncSocket + Send,
+{
+    /// Link a new account as a secondary device named `device_name`, showing the QR code to
+    /// scan on `display`. Returns once the code has been scanned and the account is set up.
+    pub async fn link_device(&mut self, device_name: &str, display: QrDisplay) -> Result<crate::types::AccountV1, crate::SocketError> {
+        let linking = self.generate_linking_uri(GenerateLinkingURIRequestV1::default(), None).await?;
+        let qr = linking.uri.and_then(LinkingQr::new)
+            .ok_or(crate::SocketError::General("Failed to encode linking URI"))?;
+        qr.show(&display)?;
+
+        self.finish_link(finish_request(linking.session_id, device_name), None).await
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl crate::blocking::Client {
+    /// Blocking version of `SocketWrapper::link_device`
+    pub fn link_device(&mut self, device_name: &str, display: QrDisplay) -> Result<crate::types::AccountV1, crate::blocking::SocketError> {
+        let linking = self.generate_linking_uri(GenerateLinkingURIRequestV1::default(), None)?;
+        let qr = linking.uri.and_then(LinkingQr::new)
+            .ok_or(crate::blocking::SocketError::General("Failed to encode linking URI"))?;
+        qr.show(&display)?;
+
+        self.finish_link(finish_request(linking.session_id, device_name), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LinkingQr;
+
+    const URI: &str = "sgnl://linkdevice?uuid=kJ1Ny7xqAt2gBnLzrUi6vQ&pub_key=BSiiS1Qr7jfYLmcc4XxlPvhuQ4aYkkq2ABhZsFgjzDd8";
+
+    #[test]
+    fn terminal() {
+        let qr = LinkingQr::new(URI).unwrap();
+        let rendered = qr.to_terminal_string();
+        let size = qr.qr.width() + 8;
+
+        assert_eq!(rendered.lines().count(), size.div_ceil(2));
+        assert!(rendered.lines().all(|line| line.chars().count() == size));
+        // The quiet zone is light all around
+        assert!(rendered.lines().take(2).all(|line| line.chars().all(|c| c == '█')));
+        assert!(rendered.lines().all(|line| line.starts_with("████") && line.ends_with("████")));
+    }
+
+    #[test]
+    fn png() {
+        let qr = LinkingQr::new(URI).unwrap();
+        let path = std::env::temp_dir().join(format!("signald-link-{}.png", std::process::id()));
+        qr.save_png(&path, 4).unwrap();
+
+        let image = image::open(&path).unwrap().to_luma8();
+        let size = (qr.qr.width() as u32 + 8) * 4;
+        assert_eq!(image.dimensions(), (size, size));
+        assert_eq!(image.get_pixel(15, 15).0, [255]);
+        // Top left finder pattern
+        assert_eq!(image.get_pixel(16, 16).0, [0]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+    #[test]
+    fn link_flow() -> Result<(), crate::SocketError> {
+        crate::test_support::block_on(async {
+            let path = std::env::temp_dir().join(format!("signald-link-flow-{}.png", std::process::id()));
+            let mut socket = crate::actions::SocketWrapper::new(crate::test_support::ScriptedSocket::new(vec![
+                serde_json::json!({ "uri": "sgnl://linkdevice?uuid=a&pub_key=b", "session_id": "session" }),
+                serde_json::json!({ "account_id": "+15550000000", "device_id": 2 })
+            ]));
+
+            let account = socket.link_device("server-1", super::QrDisplay::Png(path.clone())).await?;
+            assert_eq!(account.device_id, Some(2));
+            assert!(path.exists());
+
+            let finish = &socket.socket.sent[1];
+            assert_eq!(finish["type"], "finish_link");
+            assert_eq!(finish["session_id"], "session");
+            assert_eq!(finish["device_name"], "server-1");
+
+            std::fs::remove_file(&path)?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,197 @@
+//! Composing and rendering messages with mentions.
+//!
+//! Signal replaces each mention in a message body with a placeholder character, and locates
+//! mentions by their offset and length in UTF-16 code units. [`Composer`] builds a body and
+//! its mentions from plain text, and [`render_mentions`] does the reverse for received
+//! messages.
+
+use std::fmt::Display;
+
+use crate::types::{JsonAddressV1, JsonDataMessageV1, JsonMentionV1, SendRequestV1};
+
+/// The character Signal puts in a message body where a mention is
+pub const MENTION_PLACEHOLDER: char = '\u{fffc}';
+
+/// The placeholder that [`Composer::template`] replaces with mentions
+pub const TEMPLATE_MENTION: &str = "{mention}";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MentionError {
+    /// A mentioned address has no uuid
+    MissingUuid,
+    /// A template has a different number of mentions than addresses were given
+    MentionCount
+}
+
+impl Display for MentionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MentionError::MissingUuid => write!(f, "Mentioned address has no uuid"),
+            MentionError::MentionCount => write!(f, "Template doesn't match the number of mentions")
+        }
+    }
+}
+
+/// Builds a message body and its mentions
+#[derive(Default)]
+pub struct Composer {
+    body: String,
+    /// Length of the body in UTF-16 code units
+    len: i32,
+    mentions: Vec<JsonMentionV1>
+}
+
+impl Composer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compose a message from a template, replacing each `{mention}` with the next address
+    pub fn template(template: &str, addresses: &[JsonAddressV1]) -> Result<Self, MentionError> {
+        let mut parts = template.split(TEMPLATE_MENTION);
+        if parts.clone().count() != addresses.len() + 1 {
+            return Err(MentionError::MentionCount);
+        }
+
+        let mut composer = Composer::new().text(parts.next().unwrap_or_default());
+        for (address, part) in addresses.iter().zip(parts) {
+            composer = composer.mention(address)?.text(part);
+        }
+
+        Ok(composer)
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        self.body.push_str(text);
+        self.len += text.encode_utf16().count() as i32;
+        self
+    }
+
+    pub fn mention(self, address: &JsonAddressV1) -> Result<Self, MentionError> {
+        let uuid = address.uuid.as_deref().ok_or(MentionError::MissingUuid)?;
+        Ok(self.mention_uuid(uuid))
+    }
+
+    pub fn mention_uuid(mut self, uuid: &str) -> Self {
+        self.mentions.push(JsonMentionV1 {
+            uuid: Some(uuid.to_owned()),
+            start: Some(self.len),
+            length: Some(1)
+        });
+        self.body.push(MENTION_PLACEHOLDER);
+        self.len += 1;
+        self
+    }
+
+    /// The body and mentions
+    pub fn build(self) -> (String, Vec<JsonMentionV1>) {
+        (self.body, self.mentions)
+    }
+
+    /// Set the body and mentions of `request`
+    pub fn apply(self, request: &mut SendRequestV1) {
+        let (body, mentions) = self.build();
+        request.message_body = Some(body);
+        request.mentions = Some(mentions);
+    }
+}
+
+/// Replace the mentions in `body` with display names. `name` is given the uuid of each
+/// mentioned account, and mentions it returns `None` for are rendered as their uuid.
+pub fn render_mentions<F>(body: &str, mentions: &[JsonMentionV1], mut name: F) -> String
+where F: FnMut(&str) -> Option<String>,
+{
+    let mut mentions: Vec<_> = mentions.iter().collect();
+    mentions.sort_by_key(|mention| mention.start.unwrap_or_default());
+    let mut mentions = mentions.into_iter().peekable();
+
+    let mut rendered = String::with_capacity(body.len());
+    let mut offset = 0;
+    // End of the mention currently being replaced
+    let mut skip_until = 0;
+
+    for c in body.chars() {
+        if let Some(mention) = mentions.next_if(|mention| mention.start.unwrap_or_default() <= offset) {
+            let uuid = mention.uuid.as_deref().unwrap_or_default();
+            rendered.push('@');
+            rendered.push_str(&name(uuid).unwrap_or_else(|| uuid.to_owned()));
+            skip_until = mention.start.unwrap_or_default() + mention.length.unwrap_or(1);
+        }
+
+        if offset >= skip_until {
+            rendered.push(c);
+        }
+        offset += c.len_utf16() as i32;
+    }
+
+    rendered
+}
+
+impl JsonDataMessageV1 {
+    /// The body with mentions replaced by display names, see [`render_mentions`]
+    pub fn render_mentions<F>(&self, name: F) -> Option<String>
+    where F: FnMut(&str) -> Option<String>,
+    {
+        let body = self.body.as_deref()?;
+        Some(render_mentions(body, self.mentions.as_deref().unwrap_or_default(), name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_mentions, Composer, MentionError};
+    use crate::types::{JsonAddressV1, JsonMentionV1};
+
+    fn address(uuid: &str) -> JsonAddressV1 {
+        JsonAddressV1 {
+            uuid: Some(uuid.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn utf16_offsets() {
+        let (body, mentions) = Composer::template("🎉 {mention} and {mention}!", &[address("a"), address("b")])
+            .unwrap()
+            .build();
+
+        assert_eq!(body, "🎉 \u{fffc} and \u{fffc}!");
+        // The emoji is two UTF-16 code units
+        assert_eq!(mentions[0].start, Some(3));
+        assert_eq!(mentions[1].start, Some(9));
+
+        let rendered = render_mentions(&body, &mentions, |uuid| match uuid {
+            "a" => Some("Alice".to_owned()),
+            _ => None
+        });
+        assert_eq!(rendered, "🎉 @Alice and @b!");
+    }
+
+    #[test]
+    fn non_latin_text() {
+        let (body, mentions) = Composer::new()
+            .text("привет ")
+            .mention(&address("a")).unwrap()
+            .text(" 你好")
+            .build();
+        assert_eq!(mentions[0].start, Some(7));
+
+        // Mentions from signald may leave out a start of 0
+        let mut mentions = mentions;
+        mentions.insert(0, JsonMentionV1 { uuid: Some("b".to_owned()), start: None, length: Some(1) });
+        let body = format!("\u{fffc}{}", body);
+        mentions[1].start = Some(8);
+
+        let rendered = render_mentions(&body, &mentions, |uuid| Some(uuid.to_uppercase()));
+        assert_eq!(rendered, "@Bпривет @A 你好");
+    }
+
+    #[test]
+    fn template_errors() {
+        assert_eq!(Composer::template("{mention}", &[]).err(), Some(MentionError::MentionCount));
+        assert_eq!(
+            Composer::template("{mention}", &[JsonAddressV1::default()]).err(),
+            Some(MentionError::MissingUuid)
+        );
+    }
+}
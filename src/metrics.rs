@@ -0,0 +1,122 @@
+//! Hooks for collecting client-side metrics.
+//!
+//! Install a [`MetricsHook`] once at startup with [`set_hook`] and it will be called for every
+//! request made through a client, every connection opened and every incoming message. With the
+//! `metrics` feature enabled, [`MetricsFacade`] forwards everything to the
+//! [metrics](https://docs.rs/metrics) crate.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static HOOK: OnceLock<Box<dyn MetricsHook>> = OnceLock::new();
+
+/// Receives metrics events from clients. All methods default to doing nothing.
+pub trait MetricsHook: Send + Sync {
+    /// A request finished. `error_type` is the signald error type if signald returned an error,
+    /// or `"SocketError"` if the request failed before a response arrived.
+    fn request_completed(&self, _action: &str, _duration: Duration, _error_type: Option<&str>) {}
+
+    /// A connection to signald was opened. Any connection past the first is a reconnect.
+    fn connected(&self) {}
+
+    /// An incoming message was received for `account`
+    fn message_received(&self, _account: Option<&str>) {}
+}
+
+/// Install the global metrics hook. Returns `false` if a hook was already installed.
+pub fn set_hook<H: MetricsHook + 'static>(hook: H) -> bool {
+    HOOK.set(Box::new(hook)).is_ok()
+}
+
+pub(crate) fn hook() -> Option<&'static dyn MetricsHook> {
+    HOOK.get().map(|hook| hook.as_ref())
+}
+
+pub(crate) fn connected() {
+    if let Some(hook) = hook() {
+        hook.connected();
+    }
+}
+
+// The tokio backend doesn't dispatch incoming messages
+#[cfg(any(feature = "async-std", feature = "smol", feature = "blocking"))]
+pub(crate) fn message_received(account: Option<&str>) {
+    if let Some(hook) = hook() {
+        hook.message_received(account);
+    }
+}
+
+/// Forwards metrics to the `metrics` crate facade:
+///
+/// * `signald_request_duration_seconds` histogram, labelled by `action`
+/// * `signald_request_errors_total` counter, labelled by `action` and `error_type`
+/// * `signald_connections_total` counter
+/// * `signald_messages_received_total` counter, labelled by `account`
+#[cfg(feature = "metrics")]
+pub struct MetricsFacade;
+
+#[cfg(feature = "metrics")]
+impl MetricsHook for MetricsFacade {
+    fn request_completed(&self, action: &str, duration: Duration, error_type: Option<&str>) {
+        ::metrics::histogram!("signald_request_duration_seconds", "action" => action.to_owned())
+            .record(duration.as_secs_f64());
+
+        if let Some(error_type) = error_type {
+            ::metrics::counter!(
+                "signald_request_errors_total",
+                "action" => action.to_owned(),
+                "error_type" => error_type.to_owned()
+            ).increment(1);
+        }
+    }
+
+    fn connected(&self) {
+        ::metrics::counter!("signald_connections_total").increment(1);
+    }
+
+    fn message_received(&self, account: Option<&str>) {
+        ::metrics::counter!(
+            "signald_messages_received_total",
+            "account" => account.unwrap_or_default().to_owned()
+        ).increment(1);
+    }
+}
+
+#[cfg(all(test, any(feature = "async-std", feature = "tokio", feature = "smol")))]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use serde_json::json;
+    use uuid::Uuid;
+    use super::{set_hook, MetricsHook};
+    use crate::test_support::block_on;
+
+    static ERRORS: Mutex<Vec<(String, Option<String>)>> = Mutex::new(Vec::new());
+
+    struct Recorder;
+
+    impl MetricsHook for Recorder {
+        fn request_completed(&self, action: &str, _duration: Duration, error_type: Option<&str>) {
+            ERRORS.lock().unwrap().push((action.to_owned(), error_type.map(str::to_owned)));
+        }
+    }
+
+    #[test]
+    fn request_error_types() {
+        block_on(async {
+            set_hook(Recorder);
+
+            let id = Uuid::new_v4();
+            let _ = crate::trace::request(&id, "send", async {
+                Ok::<_, ()>(json!({ "error": {}, "error_type": "RateLimitError" }))
+            }).await;
+            let _ = crate::trace::request(&id, "version", async { Ok::<_, ()>(json!({})) }).await;
+            let _ = crate::trace::request(&id, "react", async { Err(()) }).await;
+
+            let errors = ERRORS.lock().unwrap();
+            assert!(errors.contains(&("send".to_owned(), Some("RateLimitError".to_owned()))));
+            assert!(errors.contains(&("version".to_owned(), None)));
+            assert!(errors.contains(&("react".to_owned(), Some("SocketError".to_owned()))));
+        })
+    }
+}
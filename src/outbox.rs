@@ -0,0 +1,338 @@
+//! Durable outgoing message queue.
+//!
+//! An [`Outbox`] persists every queued [`SendRequestV1`] to a file before trying to send it,
+//! so messages survive signald outages and restarts of the process. Messages to the same
+//! conversation are always delivered in the order they were queued; a message that can't be
+//! sent yet holds back the messages queued after it for that conversation, but not for others.
+//! When a group message reaches some members but fails for others, later attempts only send it
+//! to the members that failed.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::actions::SocketWrapper;
+use crate::conversation::ConversationRequest;
+use crate::retry::{is_network_failure, merge_results, resend_failed, RetryPolicy};
+use crate::socket::{AsyncSocket, MessageCommon};
+use crate::types::{JsonSendMessageResultV1, SendRequestV1, SendResponseV1};
+use crate::SocketError;
+use crate::util::{load_json, now, save_json};
+
+#[derive(Serialize, Deserialize, Default)]
+struct State {
+    next_id: u64,
+    messages: Vec<QueuedMessage>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct QueuedMessage {
+    id: u64,
+    request: SendRequestV1,
+    attempts: u32,
+    /// Milliseconds since the unix epoch
+    next_attempt: u64,
+    /// The response so far when some recipients had a network failure, so that only they are
+    /// sent the message again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<Value>
+}
+
+/// The final outcome of a queued message
+pub struct Delivery {
+    /// Id returned by [`Outbox::push`]
+    pub id: u64,
+    pub request: SendRequestV1,
+    pub status: DeliveryStatus
+}
+
+pub enum DeliveryStatus {
+    /// Signald accepted the message, with the result for each recipient
+    Sent(Vec<JsonSendMessageResultV1>),
+    /// The message was dropped after a permanent error, or after running out of retries. The
+    /// error type is the signald error type, or `"SocketError"` if signald couldn't be reached.
+    Failed { error_type: String }
+}
+
+pub struct Outbox {
+    path: PathBuf,
+    state: State,
+    retry: RetryPolicy
+}
+
+impl Outbox {
+    /// Open the queue stored at `path`, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, SocketError> {
+        let path = path.as_ref().to_path_buf();
+        let state = load_json(&path, "Failed to parse outbox file")?.unwrap_or_default();
+
+        Ok(Outbox {
+            path,
+            state,
+            retry: RetryPolicy::default()
+        })
+    }
+
+    /// Set the backoff used between attempts. A message is failed once it has been retried
+    /// `max_retries` times.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Queue a message, returning its id. The message is given a timestamp if it doesn't have
+    /// one, so that recipients can discard duplicates if a retry resends it.
+    pub fn push(&mut self, mut request: SendRequestV1) -> Result<u64, SocketError> {
+        if request.timestamp.is_none() {
+            request.timestamp = Some(now() as i64);
+        }
+
+        let id = self.state.next_id;
+        self.state.next_id += 1;
+        self.state.messages.push(QueuedMessage {
+            id,
+            request,
+            attempts: 0,
+            next_attempt: 0,
+            response: None
+        });
+
+        self.save()?;
+        Ok(id)
+    }
+
+    /// Number of messages waiting to be delivered
+    pub fn len(&self) -> usize {
+        self.state.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.messages.is_empty()
+    }
+
+    /// Try to send every message that is due, returning the ones that are finished, either
+    /// sent or failed for good. Errors are only returned if the queue can't be saved.
+    pub async fn deliver<T>(&mut self, socket: &mut SocketWrapper<T>) -> Result<Vec<Delivery>, SocketError>
+    where T: AsyncSocket + Send,
+    {
+        let now = now();
+        let mut blocked = HashSet::new();
+        let mut deliveries = Vec::new();
+        let mut index = 0;
+
+        while index < self.state.messages.len() {
+            let message = &self.state.messages[index];
+            let conversation = message.request.conversation();
+            if blocked.contains(&conversation) || message.next_attempt > now {
+                blocked.insert(conversation);
+                index += 1;
+                continue;
+            }
+
+            let mut request = serde_json::to_value(&message.request).unwrap();
+            if let Some(response) = &message.response {
+                resend_failed(&mut request, response, is_network_failure);
+            }
+
+            let result = send(socket, request).await;
+            let message = &mut self.state.messages[index];
+            let out_of_retries = message.attempts >= self.retry.max_retries;
+            let status = match result {
+                Ok(retry) => {
+                    let response = match message.response.take() {
+                        Some(mut response) => {
+                            merge_results(&mut response, retry, is_network_failure);
+                            response
+                        },
+                        None => retry
+                    };
+
+                    let results = serde_json::from_value::<SendResponseV1>(response.clone()).unwrap().results.unwrap_or_default();
+                    if results.iter().any(|result| result.network_failure == Some(true)) && !out_of_retries {
+                        message.response = Some(response);
+                        None
+                    } else {
+                        Some(DeliveryStatus::Sent(results))
+                    }
+                },
+                Err(SocketError::Signald(e)) => {
+                    if e.error_type.starts_with("RateLimit") && !out_of_retries {
+                        None
+                    } else {
+                        Some(DeliveryStatus::Failed { error_type: e.error_type })
+                    }
+                },
+                Err(_) if !out_of_retries => None,
+                Err(_) => Some(DeliveryStatus::Failed { error_type: "SocketError".to_owned() })
+            };
+
+            match status {
+                Some(status) => {
+                    let message = self.state.messages.remove(index);
+                    deliveries.push(Delivery {
+                        id: message.id,
+                        request: message.request,
+                        status
+                    });
+                },
+                None => {
+                    debug!("Failed to send queued message {}, attempt {}", message.id, message.attempts + 1);
+                    message.next_attempt = now + self.retry.delay(message.attempts).as_millis() as u64;
+                    message.attempts += 1;
+                    blocked.insert(conversation);
+                    index += 1;
+                }
+            }
+
+            self.save()?;
+        }
+
+        Ok(deliveries)
+    }
+
+    /// Deliver messages until the queue is empty, waiting out the backoff between attempts
+    pub async fn flush<T>(&mut self, socket: &mut SocketWrapper<T>) -> Result<Vec<Delivery>, SocketError>
+    where T: AsyncSocket + Send,
+    {
+        let mut deliveries = Vec::new();
+
+        loop {
+            deliveries.append(&mut self.deliver(socket).await?);
+
+            let next_attempt = match self.state.messages.iter().map(|message| message.next_attempt).min() {
+                Some(next_attempt) => next_attempt,
+                None => { return Ok(deliveries); }
+            };
+
+            crate::sleep(Duration::from_millis(next_attempt.saturating_sub(now()))).await;
+        }
+    }
+
+    fn save(&self) -> Result<(), SocketError> {
+        save_json(&self.path, &self.state)
+    }
+}
+
+/// Send a request built as JSON, which unlike [`SendRequestV1`] can limit a group send to some of
+/// its `members`
+async fn send<T>(socket: &mut SocketWrapper<T>, request: Value) -> Result<Value, SocketError>
+where T: AsyncSocket + Send,
+{
+    let id = Uuid::new_v4();
+    let msg = MessageCommon::new(id.to_simple().to_string(), "send".to_owned(), "v1".to_owned(), request);
+    let response = crate::trace::request(&id, "send", socket.call(&msg, &id)).await?;
+
+    match response.get("error") {
+        None => Ok(response),
+        Some(_) => Err(SocketError::Signald(serde_json::from_value(response).unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use serde_json::json;
+    use super::{DeliveryStatus, Outbox};
+    use crate::actions::SocketWrapper;
+    use crate::retry::RetryPolicy;
+    use crate::test_support::{block_on, ScriptedSocket};
+    use crate::types::{JsonAddressV1, SendRequestV1};
+    use crate::SocketError;
+
+    fn message(number: &str, body: &str) -> SendRequestV1 {
+        SendRequestV1 {
+            recipient_address: Some(JsonAddressV1 {
+                number: Some(number.to_owned()),
+                ..Default::default()
+            }),
+            message_body: Some(body.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ordered_and_persistent() -> Result<(), SocketError> {
+        block_on(async {
+            let path = std::env::temp_dir().join(format!("signald-outbox-{}.json", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+
+            {
+                let mut outbox = Outbox::open(&path)?;
+                outbox.push(message("+15550000001", "first"))?;
+                outbox.push(message("+15550000001", "second"))?;
+                outbox.push(message("+15550000002", "other"))?;
+            }
+
+            let mut outbox = Outbox::open(&path)?
+                .with_retry_policy(RetryPolicy::new().base_delay(Duration::from_millis(1)));
+            assert_eq!(outbox.len(), 3);
+
+            // The first message fails, which holds back the second but not the other conversation
+            let mut socket = SocketWrapper::new(ScriptedSocket::new(Vec::new()));
+            let deliveries = outbox.deliver(&mut socket).await?;
+            assert!(deliveries.is_empty());
+            assert_eq!(socket.socket.sent.len(), 2);
+            assert_eq!(socket.socket.sent[1]["messageBody"], "other");
+
+            socket.socket.sent.clear();
+            socket.socket.responses = vec![json!({ "results": [] }); 3].into();
+            let deliveries = outbox.flush(&mut socket).await?;
+            let bodies: Vec<_> = socket.socket.sent.iter()
+                .map(|sent| sent["messageBody"].as_str().unwrap())
+                .filter(|body| *body != "other")
+                .collect();
+            assert_eq!(bodies, vec!["first", "second"]);
+            assert_eq!(deliveries.len(), 3);
+            assert!(deliveries.iter().all(|delivery| matches!(delivery.status, DeliveryStatus::Sent(_))));
+            assert!(Outbox::open(&path)?.is_empty());
+
+            std::fs::remove_file(&path)?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn resends_to_failed_members() -> Result<(), SocketError> {
+        block_on(async {
+            let path = std::env::temp_dir().join(format!("signald-outbox-members-{}.json", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+
+            let mut outbox = Outbox::open(&path)?
+                .with_retry_policy(RetryPolicy::new().base_delay(Duration::from_millis(1)));
+            outbox.push(SendRequestV1 {
+                recipient_group_id: Some("group".to_owned()),
+                message_body: Some("hello".to_owned()),
+                ..Default::default()
+            })?;
+
+            let mut socket = SocketWrapper::new(ScriptedSocket::new(vec![
+                json!({ "results": [
+                    { "address": { "number": "+15550000001" }, "success": {} },
+                    { "address": { "number": "+15550000002" }, "networkFailure": true }
+                ] }),
+                json!({ "results": [
+                    { "address": { "number": "+15550000002" }, "success": {} }
+                ] })
+            ]));
+            let deliveries = outbox.flush(&mut socket).await?;
+
+            assert_eq!(socket.socket.sent.len(), 2);
+            assert!(socket.socket.sent[0].get("members").is_none());
+            assert_eq!(socket.socket.sent[1]["members"], json!([{ "number": "+15550000002" }]));
+            assert_eq!(socket.socket.sent[1]["timestamp"], socket.socket.sent[0]["timestamp"]);
+            match &deliveries[0].status {
+                DeliveryStatus::Sent(results) => {
+                    assert_eq!(results.len(), 2);
+                    assert!(results.iter().all(|result| result.network_failure.is_none()));
+                },
+                DeliveryStatus::Failed { .. } => panic!("message should have been sent")
+            }
+
+            std::fs::remove_file(&path)?;
+            Ok(())
+        })
+    }
+}
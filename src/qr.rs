@@ -0,0 +1,78 @@
+//! QR codes for the terminal and PNG files, as used for linking devices and verifying safety
+//! numbers.
+
+use std::path::Path;
+use image::{GrayImage, Luma};
+use qrcode::{Color, QrCode};
+
+/// Modules of light border around the code, the minimum the QR code standard asks for so
+/// scanners can find it
+const QUIET_ZONE: usize = 4;
+
+pub struct Qr {
+    code: QrCode
+}
+
+impl Qr {
+    /// Returns `None` if `data` is too long to encode
+    pub fn new<D: AsRef<[u8]>>(data: D) -> Option<Self> {
+        let code = QrCode::new(data.as_ref()).ok()?;
+        Some(Qr { code })
+    }
+
+    /// Width of the code in modules, not counting the quiet zone
+    pub fn width(&self) -> usize {
+        self.code.width()
+    }
+
+    /// Whether the module at `x`, `y` is dark, counting the quiet zone
+    fn is_dark(&self, colors: &[Color], x: usize, y: usize) -> bool {
+        let width = self.code.width();
+        match (x.checked_sub(QUIET_ZONE), y.checked_sub(QUIET_ZONE)) {
+            (Some(x), Some(y)) if x < width && y < width => colors[y * width + x] == Color::Dark,
+            _ => false
+        }
+    }
+
+    /// Render the code with half block characters, two modules to a line. Light modules are
+    /// drawn in the text colour and dark modules are left as background, so the code only
+    /// scans on terminals with light text on a dark background. On light themes it comes out
+    /// inverted, which many scanners reject; use [`save_png`](Self::save_png) there instead.
+    pub fn to_terminal_string(&self) -> String {
+        let colors = self.code.to_colors();
+        let size = self.code.width() + 2 * QUIET_ZONE;
+        let mut rendered = String::new();
+
+        for y in (0..size).step_by(2) {
+            for x in 0..size {
+                let top = self.is_dark(&colors, x, y);
+                let bottom = y + 1 < size && self.is_dark(&colors, x, y + 1);
+                rendered.push(match (top, bottom) {
+                    (true, true) => ' ',
+                    (true, false) => '▄',
+                    (false, true) => '▀',
+                    (false, false) => '█'
+                });
+            }
+            rendered.push('\n');
+        }
+
+        rendered
+    }
+
+    /// Write the code to a PNG file, with each module `scale` pixels wide
+    pub fn save_png<P: AsRef<Path>>(&self, path: P, scale: u32) -> Result<(), std::io::Error> {
+        let colors = self.code.to_colors();
+        let size = (self.code.width() + 2 * QUIET_ZONE) as u32;
+
+        let image = GrayImage::from_fn(size * scale, size * scale, |x, y| {
+            match self.is_dark(&colors, (x / scale) as usize, (y / scale) as usize) {
+                true => Luma([0]),
+                false => Luma([255])
+            }
+        });
+
+        image.save_with_format(path, image::ImageFormat::Png)
+            .map_err(std::io::Error::other)
+    }
+}
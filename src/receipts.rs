@@ -0,0 +1,305 @@
+//! Delivery and read status tracking.
+//!
+//! A [`ReceiptTracker`] remembers sent messages by timestamp and recipient, and advances their
+//! state as delivery, read and viewed receipts arrive from the incoming message handler. Clones
+//! share their state, so a receipt handled by the clone given to the handler wakes anyone waiting
+//! on another. Only the most recent messages are kept, see [`ReceiptTracker::capacity`].
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::address::same_address;
+use crate::types::{IncomingMessageV1, JsonAddressV1, SendResponseV1};
+
+/// How many messages are tracked, unless configured otherwise
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// The state of a sent message for one recipient, in the order receipts can advance it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReceiptState {
+    Sent,
+    Delivered,
+    Read,
+    Viewed
+}
+
+impl ReceiptState {
+    /// Parse a receipt type as sent by signald
+    pub fn from_receipt_type(receipt_type: &str) -> Option<Self> {
+        match receipt_type {
+            "DELIVERY" => Some(ReceiptState::Delivered),
+            "READ" => Some(ReceiptState::Read),
+            "VIEWED" => Some(ReceiptState::Viewed),
+            _ => None
+        }
+    }
+}
+
+struct Inner {
+    capacity: usize,
+    /// Recipients of each message by timestamp, so the oldest message comes first
+    messages: BTreeMap<i64, Vec<(JsonAddressV1, ReceiptState)>>,
+    wakers: Vec<Waker>
+}
+
+impl Default for Inner {
+    fn default() -> Self {
+        Inner {
+            capacity: DEFAULT_CAPACITY,
+            messages: BTreeMap::new(),
+            wakers: Vec::new()
+        }
+    }
+}
+
+impl Inner {
+    /// Wake every pending [`WaitFor`] so it can check its message again
+    fn wake(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct ReceiptTracker {
+    inner: Arc<Mutex<Inner>>
+}
+
+impl ReceiptTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many messages to track. Once more are sent, the oldest are forgotten. Defaults to
+    /// 10,000.
+    pub fn capacity(self, capacity: usize) -> Self {
+        self.inner.lock().unwrap().capacity = capacity;
+        self
+    }
+
+    /// Start tracking a sent message for every recipient it was successfully sent to. A message
+    /// that reached nobody isn't tracked, so waiting on it resolves to `false`.
+    pub fn record_sent(&self, response: &SendResponseV1) {
+        let timestamp = match response.timestamp {
+            Some(timestamp) => timestamp,
+            None => { return; }
+        };
+
+        let recipients: Vec<_> = response.results.iter()
+            .flatten()
+            .filter(|result| result.success.is_some())
+            .filter_map(|result| result.address.clone())
+            .map(|address| (address, ReceiptState::Sent))
+            .collect();
+        if recipients.is_empty() {
+            debug!("Not tracking message {}, no recipient received it", timestamp);
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.messages.insert(timestamp, recipients);
+        if inner.messages.len() > inner.capacity {
+            while inner.messages.len() > inner.capacity {
+                inner.messages.pop_first();
+            }
+            inner.wake();
+        }
+    }
+
+    /// Update tracked messages from an incoming receipt. Other messages are ignored.
+    pub fn handle(&self, msg: &IncomingMessageV1) {
+        let (receipt, source) = match (&msg.receipt_message, &msg.source) {
+            (Some(receipt), Some(source)) => (receipt, source),
+            _ => { return; }
+        };
+
+        let state = match receipt.type_.as_deref().and_then(ReceiptState::from_receipt_type) {
+            Some(state) => state,
+            None => { return; }
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        for timestamp in receipt.timestamps.iter().flatten() {
+            let recipients = match inner.messages.get_mut(timestamp) {
+                Some(recipients) => recipients,
+                None => continue
+            };
+
+            for (address, recipient_state) in recipients.iter_mut() {
+                if same_address(address, source) && *recipient_state < state {
+                    *recipient_state = state;
+                }
+            }
+        }
+
+        inner.wake();
+    }
+
+    /// The state of a message for a single recipient
+    pub fn recipient_state(&self, timestamp: i64, recipient: &JsonAddressV1) -> Option<ReceiptState> {
+        self.inner.lock().unwrap().messages.get(&timestamp)?
+            .iter()
+            .find(|(address, _)| same_address(address, recipient))
+            .map(|(_, state)| *state)
+    }
+
+    /// The state every recipient of a message has reached, e.g. a group message is only `Read`
+    /// once all members have read it
+    pub fn state(&self, timestamp: i64) -> Option<ReceiptState> {
+        self.inner.lock().unwrap().messages.get(&timestamp)?
+            .iter()
+            .map(|(_, state)| *state)
+            .min()
+    }
+
+    /// Stop tracking a message
+    pub fn forget(&self, timestamp: i64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.messages.remove(&timestamp);
+        inner.wake();
+    }
+
+    /// Wait until every recipient of a message has reached `state`. Resolves to `false` if the
+    /// message isn't tracked, or stops being tracked while waiting.
+    pub fn wait_for(&self, timestamp: i64, state: ReceiptState) -> WaitFor {
+        WaitFor {
+            tracker: self.clone(),
+            timestamp,
+            state
+        }
+    }
+}
+
+/// Future returned by [`ReceiptTracker::wait_for`]
+pub struct WaitFor {
+    tracker: ReceiptTracker,
+    timestamp: i64,
+    state: ReceiptState
+}
+
+impl Future for WaitFor {
+    type Output = bool;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<bool> {
+        let mut inner = self.tracker.inner.lock().unwrap();
+        let reached = inner.messages.get(&self.timestamp)
+            .and_then(|recipients| recipients.iter().map(|(_, state)| *state).min());
+
+        match reached {
+            None => Poll::Ready(false),
+            Some(reached) if reached >= self.state => Poll::Ready(true),
+            Some(_) => {
+                inner.wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReceiptState, ReceiptTracker};
+    use crate::types::{IncomingMessageV1, JsonAddressV1, JsonSendMessageResultV1, ReceiptMessageV1, SendResponseV1, SendSuccessV1};
+
+    fn address(number: &str) -> JsonAddressV1 {
+        JsonAddressV1 {
+            number: Some(number.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn sent(numbers: &[&str]) -> SendResponseV1 {
+        sent_at(100, numbers)
+    }
+
+    fn sent_at(timestamp: i64, numbers: &[&str]) -> SendResponseV1 {
+        SendResponseV1 {
+            timestamp: Some(timestamp),
+            results: Some(numbers.iter().map(|number| JsonSendMessageResultV1 {
+                address: Some(address(number)),
+                success: Some(SendSuccessV1::default()),
+                ..Default::default()
+            }).collect())
+        }
+    }
+
+    fn receipt(number: &str, receipt_type: &str) -> IncomingMessageV1 {
+        IncomingMessageV1 {
+            source: Some(address(number)),
+            receipt_message: Some(ReceiptMessageV1 {
+                type_: Some(receipt_type.to_owned()),
+                timestamps: Some(vec![100]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn receipts_advance_state() {
+        let tracker = ReceiptTracker::new();
+        tracker.record_sent(&sent(&["+15550000001", "+15550000002"]));
+        assert_eq!(tracker.state(100), Some(ReceiptState::Sent));
+
+        tracker.handle(&receipt("+15550000001", "READ"));
+        tracker.handle(&receipt("+15550000002", "DELIVERY"));
+        assert_eq!(tracker.recipient_state(100, &address("+15550000001")), Some(ReceiptState::Read));
+        assert_eq!(tracker.state(100), Some(ReceiptState::Delivered));
+
+        // Receipts never move a message backwards
+        tracker.handle(&receipt("+15550000001", "DELIVERY"));
+        assert_eq!(tracker.recipient_state(100, &address("+15550000001")), Some(ReceiptState::Read));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+    #[test]
+    fn failed_sends_are_not_tracked() {
+        let tracker = ReceiptTracker::new();
+        tracker.record_sent(&SendResponseV1 {
+            timestamp: Some(100),
+            results: Some(vec![JsonSendMessageResultV1 {
+                address: Some(address("+15550000001")),
+                network_failure: Some(true),
+                ..Default::default()
+            }])
+        });
+
+        assert_eq!(tracker.state(100), None);
+        assert!(!crate::test_support::block_on(tracker.wait_for(100, ReceiptState::Read)));
+    }
+
+    #[test]
+    fn forgets_oldest_messages() {
+        let tracker = ReceiptTracker::new().capacity(2);
+        for timestamp in [300, 100, 200] {
+            tracker.record_sent(&sent_at(timestamp, &["+15550000001"]));
+        }
+
+        assert_eq!(tracker.state(100), None);
+        assert_eq!(tracker.state(200), Some(ReceiptState::Sent));
+        assert_eq!(tracker.state(300), Some(ReceiptState::Sent));
+    }
+
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+    #[test]
+    fn wait_until_read() {
+        crate::test_support::block_on(async {
+            let tracker = ReceiptTracker::new();
+            tracker.record_sent(&sent(&["+15550000001"]));
+
+            let handler = tracker.clone();
+            let read = crate::test_support::spawn(async move {
+                handler.handle(&receipt("+15550000001", "DELIVERY"));
+                handler.handle(&receipt("+15550000001", "READ"));
+            });
+
+            assert!(tracker.wait_for(100, ReceiptState::Read).await);
+            assert!(!tracker.wait_for(200, ReceiptState::Read).await);
+            read.await;
+        })
+    }
+}
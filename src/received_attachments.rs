@@ -0,0 +1,133 @@
+//! Opening attachments of incoming messages.
+//!
+//! Signald downloads incoming attachments into its attachment directory and reports the path as
+//! `storedFilename`. The path is only trusted if it resolves to a file inside that directory, and
+//! the file must have the size signald reported.
+//!
+//! The `digest` of an attachment is the SHA-256 of the encrypted upload, not of the decrypted file
+//! signald stores, so it can't be checked here.
+
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "async-std")]
+use async_std::{fs::File, io::copy};
+#[cfg(feature = "tokio")]
+use tokio::{fs::File, io::copy};
+#[cfg(feature = "smol")]
+use smol::{fs::File, io::copy};
+
+use crate::types::JsonAttachmentV1;
+use crate::SocketError;
+
+/// Signald's default attachment directory, `$XDG_DATA_HOME/signald/attachments`
+pub fn default_attachment_dir() -> PathBuf {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+        .unwrap_or_default();
+
+    data_home.join("signald/attachments")
+}
+
+impl JsonAttachmentV1 {
+    /// Open a received attachment stored in signald's default attachment directory
+    pub async fn open(&self) -> Result<File, SocketError> {
+        self.open_in(default_attachment_dir()).await
+    }
+
+    /// Open a received attachment stored in `attachment_dir`
+    pub async fn open_in<P: AsRef<Path>>(&self, attachment_dir: P) -> Result<File, SocketError> {
+        let path = self.stored_path(attachment_dir.as_ref())?;
+        let file = File::open(&path).await?;
+
+        if let Some(size) = self.size {
+            if file.metadata().await?.len() != size as u64 {
+                return Err(SocketError::General("Attachment size doesn't match"));
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Copy a received attachment from `attachment_dir` to `destination`, returning the number
+    /// of bytes copied
+    pub async fn copy_to<P, Q>(&self, attachment_dir: P, destination: Q) -> Result<u64, SocketError>
+    where P: AsRef<Path>,
+          Q: AsRef<Path>,
+    {
+        let mut file = self.open_in(attachment_dir).await?;
+        let mut out = File::create(destination.as_ref()).await?;
+        let copied = copy(&mut file, &mut out).await?;
+
+        // The file could have changed since it was opened
+        if self.size.is_some_and(|size| size as u64 != copied) {
+            return Err(SocketError::General("Attachment size doesn't match"));
+        }
+
+        Ok(copied)
+    }
+
+    /// The canonical path of the stored file, if it is inside `attachment_dir`
+    fn stored_path(&self, attachment_dir: &Path) -> Result<PathBuf, SocketError> {
+        let stored = self.stored_filename.as_deref()
+            .ok_or(SocketError::General("Attachment hasn't been downloaded"))?;
+
+        let attachment_dir = std::fs::canonicalize(attachment_dir)?;
+        let path = std::fs::canonicalize(attachment_dir.join(stored))?;
+        if !path.starts_with(&attachment_dir) || !path.is_file() {
+            warn!("Refusing to open attachment outside of {}: {}", attachment_dir.display(), stored);
+            return Err(SocketError::General("Attachment is outside the attachment directory"));
+        }
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "async-std")]
+    use async_std::io::ReadExt;
+    #[cfg(feature = "tokio")]
+    use tokio::io::AsyncReadExt;
+    #[cfg(feature = "smol")]
+    use smol::io::AsyncReadExt;
+    use crate::test_support::block_on;
+    use crate::types::JsonAttachmentV1;
+    use crate::SocketError;
+
+    fn attachment(stored: &str, size: i32) -> JsonAttachmentV1 {
+        JsonAttachmentV1 {
+            stored_filename: Some(stored.to_owned()),
+            size: Some(size),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn open_and_copy() -> Result<(), SocketError> {
+        block_on(async {
+            let root = std::env::temp_dir().join(format!("signald-received-{}", std::process::id()));
+            let dir = root.join("attachments");
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(dir.join("1234"), "hello")?;
+            std::fs::write(root.join("secret"), "hello")?;
+
+            // signald reports absolute paths, but relative ones resolve against the directory too
+            let mut contents = String::new();
+            attachment(dir.join("1234").to_str().unwrap(), 5).open_in(&dir).await?
+                .read_to_string(&mut contents).await?;
+            assert_eq!(contents, "hello");
+
+            let copied = attachment("1234", 5).copy_to(&dir, root.join("copy")).await?;
+            assert_eq!(copied, 5);
+            assert_eq!(std::fs::read_to_string(root.join("copy"))?, "hello");
+
+            assert!(attachment("1234", 4).open_in(&dir).await.is_err());
+            assert!(attachment("../secret", 5).open_in(&dir).await.is_err());
+            assert!(attachment(root.join("secret").to_str().unwrap(), 5).open_in(&dir).await.is_err());
+
+            std::fs::remove_dir_all(&root)?;
+            Ok(())
+        })
+    }
+}
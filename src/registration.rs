@@ -0,0 +1,261 @@
+//! Registering a new account, one explicit step at a time.
+//!
+//! Registration takes a few round trips: requesting a code, which Signal may refuse until a
+//! captcha is solved, then verifying the code sent by SMS or voice call. [`Registration`] tracks
+//! which step an account is at, and can save it to a file so a script can be stopped while
+//! waiting for a captcha or code and continue later.
+
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+use crate::actions::SocketWrapper;
+use crate::socket::AsyncSocket;
+use crate::types::{AccountV1, RegisterRequestV1, VerifyRequestV1};
+use crate::SocketError;
+use crate::util::{load_json, now, save_json};
+
+/// Prefix of the captcha links from https://signalcaptchas.org/registration/generate.html
+const CAPTCHA_SCHEME: &str = "signalcaptcha://";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum RegistrationState {
+    /// No code has been requested yet
+    Start,
+    /// Signal wants a captcha solved before sending a code, see
+    /// [`submit_captcha`](Registration::submit_captcha)
+    NeedsCaptcha,
+    /// A code was sent, see [`verify`](Registration::verify)
+    AwaitingCode {
+        voice: bool,
+        /// Milliseconds since the unix epoch
+        requested_at: u64
+    },
+    /// The account is registered
+    Done {
+        uuid: Option<String>
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    account: String,
+    server: Option<String>,
+    state: RegistrationState
+}
+
+pub struct Registration {
+    path: Option<PathBuf>,
+    saved: Saved
+}
+
+impl Registration {
+    /// Start registering `account`, an e164 phone number, without saving progress
+    pub fn new<S: Into<String>>(account: S) -> Self {
+        Registration {
+            path: None,
+            saved: Saved {
+                account: account.into(),
+                server: None,
+                state: RegistrationState::Start
+            }
+        }
+    }
+
+    /// Continue registering `account` from the progress saved at `path`, or start if there is
+    /// none
+    pub fn open<P: AsRef<Path>, S: Into<String>>(path: P, account: S) -> Result<Self, SocketError> {
+        let path = path.as_ref().to_path_buf();
+        let account = account.into();
+
+        let saved = match load_json::<Saved>(&path, "Failed to parse registration file")? {
+            Some(saved) if saved.account != account => {
+                return Err(SocketError::General("Registration file is for another account"));
+            },
+            Some(saved) => saved,
+            None => Registration::new(account).saved
+        };
+
+        Ok(Registration {
+            path: Some(path),
+            saved
+        })
+    }
+
+    /// The server to register with, if not the default
+    pub fn server<S: Into<String>>(mut self, server: S) -> Self {
+        self.saved.server = Some(server.into());
+        self
+    }
+
+    pub fn account(&self) -> &str {
+        &self.saved.account
+    }
+
+    pub fn state(&self) -> &RegistrationState {
+        &self.saved.state
+    }
+
+    /// Request a verification code by SMS, or by voice call. Signal usually only allows a voice
+    /// call after an SMS code was requested, and may ask for a captcha first.
+    pub async fn request_code<T>(&mut self, socket: &mut SocketWrapper<T>, voice: bool) -> Result<&RegistrationState, SocketError>
+    where T: AsyncSocket + Send,
+    {
+        self.register(socket, None, voice).await
+    }
+
+    /// Request a verification code with a solved captcha. The token can be given with or
+    /// without its `signalcaptcha://` prefix.
+    pub async fn submit_captcha<T>(&mut self, socket: &mut SocketWrapper<T>, captcha: &str, voice: bool) -> Result<&RegistrationState, SocketError>
+    where T: AsyncSocket + Send,
+    {
+        let captcha = validate_captcha(captcha)?;
+        self.register(socket, Some(captcha.to_owned()), voice).await
+    }
+
+    async fn register<T>(&mut self, socket: &mut SocketWrapper<T>, captcha: Option<String>, voice: bool) -> Result<&RegistrationState, SocketError>
+    where T: AsyncSocket + Send,
+    {
+        if let RegistrationState::Done { .. } = self.saved.state {
+            return Err(SocketError::General("Account is already registered"));
+        }
+
+        let request = RegisterRequestV1 {
+            account: Some(self.saved.account.clone()),
+            captcha,
+            server: self.saved.server.clone(),
+            voice: Some(voice)
+        };
+
+        let state = match socket.register(request, None).await {
+            Ok(_) => RegistrationState::AwaitingCode {
+                voice,
+                requested_at: now()
+            },
+            Err(SocketError::Signald(e)) if e.error_type.starts_with("CaptchaRequired") => RegistrationState::NeedsCaptcha,
+            Err(e) => { return Err(e); }
+        };
+
+        self.set_state(state)?;
+        Ok(&self.saved.state)
+    }
+
+    /// Verify the account with the code that was sent. Dashes and spaces in the code are
+    /// ignored.
+    pub async fn verify<T>(&mut self, socket: &mut SocketWrapper<T>, code: &str) -> Result<AccountV1, SocketError>
+    where T: AsyncSocket + Send,
+    {
+        if !matches!(self.saved.state, RegistrationState::AwaitingCode { .. }) {
+            return Err(SocketError::General("No verification code has been requested"));
+        }
+
+        let code: String = code.chars().filter(|c| *c != '-' && !c.is_whitespace()).collect();
+        if code.len() != 6 || !code.chars().all(|c| c.is_ascii_digit()) {
+            return Err(SocketError::General("Verification code must be 6 digits"));
+        }
+
+        let account = socket.verify(VerifyRequestV1 {
+            account: Some(self.saved.account.clone()),
+            code: Some(code)
+        }, None).await?;
+
+        self.set_state(RegistrationState::Done {
+            uuid: account.address.as_ref().and_then(|address| address.uuid.clone())
+        })?;
+        Ok(account)
+    }
+
+    fn set_state(&mut self, state: RegistrationState) -> Result<(), SocketError> {
+        debug!("Registration of {} is now {:?}", self.saved.account, state);
+        self.saved.state = state;
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), SocketError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => { return Ok(()); }
+        };
+
+        save_json(path, &self.saved)
+    }
+}
+
+/// Check that `captcha` looks like a Signal captcha token, returning it without its scheme
+pub fn validate_captcha(captcha: &str) -> Result<&str, SocketError> {
+    let captcha = captcha.trim();
+    let token = captcha.strip_prefix(CAPTCHA_SCHEME).unwrap_or(captcha);
+
+    // Tokens look like `signal-hcaptcha.<site key>.registration.<token>`
+    let parts: Vec<_> = token.splitn(4, '.').collect();
+    let valid = parts.len() == 4
+        && parts[0].starts_with("signal-")
+        && parts.iter().all(|part| !part.is_empty())
+        && token.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+
+    if valid {
+        Ok(token)
+    } else {
+        Err(SocketError::General("Invalid captcha token, copy the whole signalcaptcha:// link"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::{validate_captcha, Registration, RegistrationState};
+    use crate::actions::SocketWrapper;
+    use crate::test_support::{block_on, ScriptedSocket};
+    use crate::SocketError;
+
+    const CAPTCHA: &str = "signalcaptcha://signal-hcaptcha.5fad97ac-7d06-4e44-b18a-b950b20148ff.registration.P1_eyJ0eXAiOiJKV1Qi.eyJwZCI6MH0.x-y_z";
+
+    #[test]
+    fn captcha_format() {
+        assert!(validate_captcha(CAPTCHA).is_ok());
+        assert_eq!(validate_captcha(&CAPTCHA[16..]).unwrap(), &CAPTCHA[16..]);
+        assert!(validate_captcha("03AGdBq24").is_err());
+        assert!(validate_captcha("signalcaptcha://signal-hcaptcha.key.registration.").is_err());
+        assert!(validate_captcha("signal-hcaptcha.key.registration.abc def").is_err());
+    }
+
+    #[test]
+    fn captcha_then_verify() -> Result<(), SocketError> {
+        block_on(async {
+            let path = std::env::temp_dir().join(format!("signald-registration-{}.json", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+
+            let mut socket = SocketWrapper::new(ScriptedSocket::new(vec![
+                    json!({
+                        "id": "",
+                        "type": "register",
+                        "error": { "more": "", "message": "a captcha token is required to register" },
+                        "error_type": "CaptchaRequiredError"
+                    }),
+                    json!({ "account_id": "+15551234567", "pending": true }),
+                    json!({ "account_id": "+15551234567", "address": { "number": "+15551234567", "uuid": "abc" } })
+                ]));
+
+            let mut registration = Registration::open(&path, "+15551234567")?;
+            assert_eq!(registration.request_code(&mut socket, false).await?, &RegistrationState::NeedsCaptcha);
+            assert!(registration.submit_captcha(&mut socket, "not a captcha", false).await.is_err());
+            assert!(matches!(
+                registration.submit_captcha(&mut socket, CAPTCHA, false).await?,
+                RegistrationState::AwaitingCode { voice: false, .. }
+            ));
+            assert_eq!(socket.socket.sent[1]["captcha"], &CAPTCHA[16..]);
+
+            // Pick up where we left off after a restart
+            let mut registration = Registration::open(&path, "+15551234567")?;
+            assert!(matches!(registration.state(), RegistrationState::AwaitingCode { .. }));
+            assert!(registration.verify(&mut socket, "12345").await.is_err());
+            registration.verify(&mut socket, "123-456").await?;
+            assert_eq!(socket.socket.sent[2]["code"], "123456");
+            assert_eq!(registration.state(), &RegistrationState::Done { uuid: Some("abc".to_owned()) });
+
+            assert!(Registration::open(&path, "+15550000000").is_err());
+            std::fs::remove_file(&path)?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,258 @@
+//! Opt-in retries for rate limits and network failures.
+//!
+//! [`RetryPolicy`] is an [`Interceptor`], so it is enabled per client with
+//! [`SocketWrapper::add_interceptor`](crate::actions::SocketWrapper::add_interceptor). Any
+//! request signald rejects with a rate limit error is retried as a whole. For `send` requests,
+//! recipients whose result reports a network failure are retried as well, and their new results
+//! are merged into the original response. Group sends are only resent to those recipients, a
+//! direct send only has the one.
+
+use std::time::Duration;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::address;
+use crate::interceptor::{Interceptor, Next};
+use crate::types::JsonAddressV1;
+use crate::SocketError;
+
+/// Exponential backoff with jitter. The delay before retry `n` is a random duration between
+/// half and all of `base_delay * 2^n`, capped at `max_delay`.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub(crate) max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60)
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Total number of retries made for a single request, across rate limits and network
+    /// failures
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn base_delay(mut self, base_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self
+    }
+
+    pub fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        delay / 2 + delay.mul_f64(fastrand::f64() / 2.0)
+    }
+
+    /// Run `request`, retrying while signald reports a rate limit
+    async fn run(&self, next: &mut Next<'_>, request: &Value, attempt: &mut u32) -> Result<Value, SocketError> {
+        loop {
+            let response = next.run(request.clone()).await?;
+
+            if !is_rate_limited(&response) || *attempt >= self.max_retries {
+                return Ok(response);
+            }
+
+            debug!("Rate limited, retry {} of {}", *attempt + 1, self.max_retries);
+            crate::sleep(self.delay(*attempt)).await;
+            *attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl Interceptor for RetryPolicy {
+    async fn intercept(&self, mut request: Value, mut next: Next<'_>) -> Result<Value, SocketError> {
+        let mut attempt = 0;
+        let mut response = self.run(&mut next, &request, &mut attempt).await?;
+
+        if request.get("type").and_then(Value::as_str) != Some("send") {
+            return Ok(response);
+        }
+
+        while has_network_failures(&response) && attempt < self.max_retries {
+            debug!("Network failure sending message, retry {} of {}", attempt + 1, self.max_retries);
+            crate::sleep(self.delay(attempt)).await;
+            attempt += 1;
+
+            resend_failed(&mut request, &response, is_network_failure);
+            let retry = self.run(&mut next, &request, &mut attempt).await?;
+            if retry.get("error").is_some() {
+                break;
+            }
+
+            merge_results(&mut response, retry, is_network_failure);
+        }
+
+        Ok(response)
+    }
+}
+
+fn is_rate_limited(response: &Value) -> bool {
+    response.get("error").is_some() && response.get("error_type")
+        .and_then(Value::as_str)
+        .is_some_and(|error_type| error_type.starts_with("RateLimit"))
+}
+
+pub(crate) fn is_network_failure(result: &Value) -> bool {
+    result.get("networkFailure").and_then(Value::as_bool).unwrap_or(false)
+}
+
+fn has_network_failures(response: &Value) -> bool {
+    response.get("results")
+        .and_then(Value::as_array)
+        .is_some_and(|results| results.iter().any(is_network_failure))
+}
+
+fn same_address(a: &Value, b: &Value) -> bool {
+    match (JsonAddressV1::deserialize(a), JsonAddressV1::deserialize(b)) {
+        (Ok(a), Ok(b)) => address::same_address(&a, &b),
+        _ => false
+    }
+}
+
+/// Prepare `request` to be sent again to the recipients whose result in `response` is `failed`.
+/// Resending with the original timestamp lets recipients that already have the message discard
+/// the duplicate.
+pub(crate) fn resend_failed(request: &mut Value, response: &Value, failed: fn(&Value) -> bool) {
+    if let Some(timestamp) = response.get("timestamp") {
+        request["timestamp"] = timestamp.clone();
+    }
+    only_failed(request, response, failed);
+}
+
+/// Limit a group `send` to the members whose result in `response` is `failed`
+fn only_failed(request: &mut Value, response: &Value, failed: fn(&Value) -> bool) {
+    if request.get("recipientGroupId").is_none_or(Value::is_null) {
+        return;
+    }
+
+    let members: Vec<Value> = response.get("results")
+        .and_then(Value::as_array)
+        .map(|results| results.iter()
+            .filter(|result| failed(result))
+            .map(|result| result["address"].clone())
+            .collect())
+        .unwrap_or_default();
+    request["members"] = Value::Array(members);
+}
+
+/// Replace the results of recipients whose result is `failed` with their results from `retry`
+pub(crate) fn merge_results(response: &mut Value, retry: Value, failed: fn(&Value) -> bool) {
+    let retried = match retry.get("results").and_then(Value::as_array) {
+        Some(results) => results,
+        None => { return; }
+    };
+
+    if let Some(results) = response.get_mut("results").and_then(Value::as_array_mut) {
+        for result in results.iter_mut().filter(|result| failed(result)) {
+            let new_result = retried.iter().find(|new_result| {
+                same_address(&result["address"], &new_result["address"])
+            });
+
+            if let Some(new_result) = new_result {
+                *result = new_result.clone();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use serde_json::{json, Value};
+    use super::RetryPolicy;
+    use crate::actions::SocketWrapper;
+    use crate::test_support::{block_on, ScriptedSocket};
+    use crate::types::SendRequestV1;
+    use crate::SocketError;
+
+    fn rate_limited() -> Value {
+        json!({
+            "id": "",
+            "type": "send",
+            "error": { "message": "rate limited", "more": "" },
+            "error_type": "RateLimitError"
+        })
+    }
+
+    fn result(number: &str, network_failure: bool) -> Value {
+        json!({ "address": { "number": number }, "networkFailure": network_failure })
+    }
+
+    #[test]
+    fn retries_rate_limits_and_network_failures() -> Result<(), SocketError> {
+        block_on(async {
+            let responses = vec![
+                rate_limited(),
+                json!({ "timestamp": 7, "results": [result("+15550000001", false), result("+15550000002", true)] }),
+                json!({ "timestamp": 7, "results": [result("+15550000001", false), result("+15550000002", false)] })
+            ];
+
+            let policy = RetryPolicy::new().base_delay(Duration::from_millis(1));
+            let mut socket = SocketWrapper::new(ScriptedSocket::new(responses))
+                .with_interceptor(policy);
+
+            let request = SendRequestV1 {
+                recipient_group_id: Some("group".to_owned()),
+                message_body: Some("hello".to_owned()),
+                ..Default::default()
+            };
+            let response = socket.send(request, None).await?;
+
+            let results = response.results.unwrap();
+            assert_eq!(results.len(), 2);
+            assert!(results.iter().all(|result| result.network_failure == Some(false)));
+
+            assert_eq!(socket.socket.sent.len(), 3);
+            assert_eq!(socket.socket.sent[2]["timestamp"], 7);
+            // Only the recipient that failed gets the message again
+            assert!(socket.socket.sent[1].get("members").is_none());
+            assert_eq!(socket.socket.sent[2]["members"], json!([{ "number": "+15550000002" }]));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() -> Result<(), SocketError> {
+        block_on(async {
+            let responses = vec![rate_limited(), rate_limited(), rate_limited()];
+
+            let policy = RetryPolicy::new().max_retries(1).base_delay(Duration::from_millis(1));
+            let mut socket = SocketWrapper::new(ScriptedSocket::new(responses))
+                .with_interceptor(policy);
+
+            match socket.version(crate::types::VersionRequestV1, None).await {
+                Err(SocketError::Signald(e)) => assert_eq!(e.error_type.as_str(), "RateLimitError"),
+                _ => panic!("Expected rate limit error")
+            }
+            assert_eq!(socket.socket.sent.len(), 2);
+
+            Ok(())
+        })
+    }
+}
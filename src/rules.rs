@@ -0,0 +1,508 @@
+//! Declarative auto-responder rules.
+//!
+//! Rules are read from a TOML or YAML file, chosen by its extension, so they can be edited by
+//! people who don't write code. Each rule has optional conditions, which must all match for the
+//! rule to fire, and actions to take when it does:
+//!
+//! ```toml
+//! [[rules]]
+//! keywords = ["hours", "open"]
+//! reply = "We're open 9:00 to 17:00, Monday to Friday."
+//!
+//! [[rules]]
+//! schedule = { days = ["sat", "sun"], from = "00:00", to = "23:59" }
+//! reply = "I'm away for the weekend."
+//! cooldown = 3600
+//!
+//! [[rules]]
+//! contains = "urgent"
+//! react = "🚨"
+//! forward_to_group = "EdSqI90cS0UomDpgUXOlCoObWvQOXlH5G3Z2d3f4ayE="
+//! ```
+//!
+//! Rules are evaluated in order against data messages with a body, except our own. A rule with
+//! `stop = true` keeps later rules from firing. The file is reloaded whenever it changes.
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::fmt::Display;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use serde::Deserialize;
+
+use crate::address::{address_of, same_address};
+use crate::conversation::{Conversation, ConversationRequest};
+use crate::types::{IncomingMessageV1, ReactRequestV1, SendRequestV1};
+
+pub enum RulesError {
+    Io(std::io::Error),
+    /// The file couldn't be parsed, with the parser's explanation
+    Parse(String)
+}
+
+impl Display for RulesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RulesError::Io(e) => write!(f, "Error: {}", e),
+            RulesError::Parse(e) => write!(f, "Invalid rules: {}", e)
+        }
+    }
+}
+
+impl std::fmt::Debug for RulesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl From<std::io::Error> for RulesError {
+    fn from(e: std::io::Error) -> Self {
+        RulesError::Io(e)
+    }
+}
+
+#[derive(Deserialize)]
+struct RulesFile {
+    #[serde(default)]
+    rules: Vec<Rule>
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Shown in logs
+    pub name: Option<String>,
+
+    /// Any of these words appears in the message, ignoring case
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// The message contains this text, ignoring case
+    pub contains: Option<String>,
+    /// The sender's number or uuid is one of these
+    #[serde(default)]
+    pub from: Vec<String>,
+    /// The message was sent to one of these groups
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// The message was sent directly (`true`) or to a group (`false`)
+    pub direct: Option<bool>,
+    /// The message arrived during this time, in local time
+    pub schedule: Option<Schedule>,
+
+    /// Reply in the same conversation
+    pub reply: Option<String>,
+    /// React to the message with this emoji
+    pub react: Option<String>,
+    /// Forward the message, with its sender, to this group
+    pub forward_to_group: Option<String>,
+
+    /// Seconds before the rule can fire again in the same conversation
+    pub cooldown: Option<u64>,
+    /// Don't evaluate later rules if this one fires
+    #[serde(default)]
+    pub stop: bool
+}
+
+/// A time window on some days of the week. Windows ending before they start wrap past midnight,
+/// and belong to the day they start on.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "ScheduleFile")]
+pub struct Schedule {
+    /// Every day if empty
+    pub days: Vec<Weekday>,
+    pub from: NaiveTime,
+    pub to: NaiveTime
+}
+
+/// A schedule as written in the rules file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleFile {
+    /// Days like `mon` or `saturday`
+    #[serde(default)]
+    days: Vec<String>,
+    /// Start time, as `HH:MM`
+    from: String,
+    /// End time, as `HH:MM`
+    to: String
+}
+
+impl TryFrom<ScheduleFile> for Schedule {
+    type Error = String;
+
+    fn try_from(file: ScheduleFile) -> Result<Self, Self::Error> {
+        let time = |time: &str| NaiveTime::parse_from_str(time, "%H:%M")
+            .map_err(|_| format!("invalid time {}, expected HH:MM", time));
+        let days = file.days.iter()
+            .map(|name| name.parse().map_err(|_| format!("invalid day {}", name)))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Schedule {
+            days,
+            from: time(&file.from)?,
+            to: time(&file.to)?
+        })
+    }
+}
+
+impl Schedule {
+    fn contains(&self, now: NaiveDateTime) -> bool {
+        let time = now.time();
+
+        // The day the window started on, which is yesterday for the early part of a wrapping
+        // window
+        let day = if self.from <= self.to {
+            if time < self.from || time > self.to {
+                return false;
+            }
+            now.weekday()
+        } else if time >= self.from {
+            now.weekday()
+        } else if time <= self.to {
+            now.weekday().pred()
+        } else {
+            return false;
+        };
+
+        self.days.is_empty() || self.days.contains(&day)
+    }
+}
+
+impl Rule {
+    fn matches(&self, msg: &IncomingMessageV1, body: &str, conversation: &Conversation, now: NaiveDateTime) -> bool {
+        let lowercase = body.to_lowercase();
+
+        if !self.keywords.is_empty() {
+            let words: Vec<_> = lowercase.split(|c: char| !c.is_alphanumeric()).collect();
+            if !self.keywords.iter().any(|keyword| words.contains(&keyword.to_lowercase().as_str())) {
+                return false;
+            }
+        }
+
+        if self.contains.as_ref().is_some_and(|text| !lowercase.contains(&text.to_lowercase())) {
+            return false;
+        }
+
+        if !self.from.is_empty() {
+            let source = msg.source.as_ref();
+            let matches = self.from.iter().any(|from| source.is_some_and(|source| same_address(source, &address_of(from))));
+            if !matches {
+                return false;
+            }
+        }
+
+        if !self.groups.is_empty() && !conversation.group_id().is_some_and(|id| self.groups.iter().any(|group| group == id)) {
+            return false;
+        }
+
+        if self.direct.is_some_and(|direct| direct != conversation.address().is_some()) {
+            return false;
+        }
+
+        self.schedule.as_ref().is_none_or(|schedule| schedule.contains(now))
+    }
+
+    fn actions(&self, msg: &IncomingMessageV1, body: &str) -> Vec<Action> {
+        let mut actions = Vec::new();
+
+        if let Some(reply) = self.reply.as_ref().and_then(|text| msg.reply(text.as_str())) {
+            actions.push(Action::Send(reply));
+        }
+
+        if let Some(react) = self.react.as_ref().and_then(|emoji| msg.react(emoji.as_str())) {
+            actions.push(Action::React(react));
+        }
+
+        if let Some(group) = &self.forward_to_group {
+            let sender = msg.source.as_ref()
+                .and_then(|source| source.number.as_deref().or(source.uuid.as_deref()))
+                .unwrap_or("unknown");
+
+            actions.push(Action::Send(SendRequestV1 {
+                username: msg.account.clone(),
+                message_body: Some(format!("{}: {}", sender, body)),
+                ..Default::default()
+            }.to(&Conversation::group(group.as_str()))));
+        }
+
+        actions
+    }
+}
+
+/// A request to make for a rule that fired
+pub enum Action {
+    Send(SendRequestV1),
+    React(ReactRequestV1)
+}
+
+pub struct RulesEngine {
+    path: PathBuf,
+    /// Hash of the file's contents when it was loaded
+    version: u64,
+    /// Version of the file that last failed to load, so it is only reported once
+    failed: Option<u64>,
+    rules: Vec<Rule>,
+    /// When each rule with a cooldown last fired, by rule index and conversation
+    fired: HashMap<(usize, Conversation), Instant>
+}
+
+impl RulesEngine {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, RulesError> {
+        let path = path.as_ref().to_path_buf();
+        let contents = fs::read_to_string(&path)?;
+        let version = version(&contents);
+        let rules = parse(&path, &contents)?;
+
+        Ok(RulesEngine {
+            path,
+            version,
+            failed: None,
+            rules,
+            fired: HashMap::new()
+        })
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Reload the rules if the file changed, returning whether it did. If the new file is
+    /// invalid, the error is returned once and the old rules are kept until the file changes
+    /// again.
+    pub fn reload_if_changed(&mut self) -> Result<bool, RulesError> {
+        let contents = fs::read_to_string(&self.path)?;
+        let current = version(&contents);
+        if current == self.version || Some(current) == self.failed {
+            return Ok(false);
+        }
+
+        let rules = match parse(&self.path, &contents) {
+            Ok(rules) => rules,
+            Err(e) => {
+                self.failed = Some(current);
+                return Err(e);
+            }
+        };
+        self.version = current;
+        self.failed = None;
+        self.rules = rules;
+        self.fired.clear();
+        Ok(true)
+    }
+
+    /// The actions for an incoming message at the current local time
+    pub fn evaluate(&mut self, msg: &IncomingMessageV1) -> Vec<Action> {
+        self.evaluate_at(msg, Local::now().naive_local())
+    }
+
+    /// The actions for an incoming message at `now`
+    pub fn evaluate_at(&mut self, msg: &IncomingMessageV1, now: NaiveDateTime) -> Vec<Action> {
+        if msg.is_own() {
+            return Vec::new();
+        }
+
+        let (body, conversation) = match (msg.data_message.as_ref().and_then(|data| data.body.as_deref()), msg.conversation()) {
+            (Some(body), Some(conversation)) => (body, conversation),
+            _ => { return Vec::new(); }
+        };
+
+        let mut actions = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            if !rule.matches(msg, body, &conversation, now) {
+                continue;
+            }
+
+            if let Some(cooldown) = rule.cooldown {
+                let key = (index, conversation.clone());
+                if self.fired.get(&key).is_some_and(|fired| fired.elapsed() < Duration::from_secs(cooldown)) {
+                    continue;
+                }
+                self.fired.insert(key, Instant::now());
+            }
+
+            debug!("Rule {} fired", rule.name.as_deref().unwrap_or(&index.to_string()));
+            actions.extend(rule.actions(msg, body));
+
+            if rule.stop {
+                break;
+            }
+        }
+
+        actions
+    }
+
+    /// Reload the rules if needed, evaluate them and send the resulting requests, returning how
+    /// many were sent
+    #[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+    pub async fn handle<T>(&mut self, socket: &mut crate::actions::SocketWrapper<T>, msg: &IncomingMessageV1) -> Result<usize, crate::SocketError>
+    where T: crate::socket::AsyncSocket + Send,
+    {
+        if let Err(e) = self.reload_if_changed() {
+            error!("Failed to reload rules from {}: {}", self.path.display(), e);
+        }
+
+        let actions = self.evaluate(msg);
+        let count = actions.len();
+        for action in actions {
+            match action {
+                Action::Send(request) => { socket.send(request, None).await?; },
+                Action::React(request) => { socket.react(request, None).await?; }
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+/// Rewriting a file can keep its modification time and length, so changes are detected by its
+/// contents
+fn version(contents: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn parse(path: &Path, contents: &str) -> Result<Vec<Rule>, RulesError> {
+    let file: RulesFile = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("yaml") | Some("yml") => serde_yaml::from_str(contents).map_err(|e| RulesError::Parse(e.to_string()))?,
+        _ => toml::from_str(contents).map_err(|e| RulesError::Parse(e.to_string()))?
+    };
+
+    Ok(file.rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use super::{Action, RulesEngine, RulesError};
+    use crate::types::{IncomingMessageV1, JsonAddressV1, JsonDataMessageV1, JsonGroupV2InfoV1};
+
+    fn message(body: &str, group: Option<&str>) -> IncomingMessageV1 {
+        IncomingMessageV1 {
+            account: Some("+15550000000".to_owned()),
+            source: Some(JsonAddressV1 {
+                number: Some("+15550000001".to_owned()),
+                ..Default::default()
+            }),
+            data_message: Some(JsonDataMessageV1 {
+                body: Some(body.to_owned()),
+                timestamp: Some(100),
+                group_v_2: group.map(|id| JsonGroupV2InfoV1 {
+                    id: Some(id.to_owned()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn summary(actions: Vec<Action>) -> Vec<String> {
+        actions.into_iter()
+            .map(|action| match action {
+                Action::Send(send) => format!(
+                    "send {} {}",
+                    send.recipient_group_id.or(send.recipient_address.and_then(|address| address.number)).unwrap(),
+                    send.message_body.unwrap()
+                ),
+                Action::React(react) => format!("react {}", react.reaction.unwrap().emoji.unwrap())
+            })
+            .collect()
+    }
+
+    fn write(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("signald-rules-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn toml_rules() {
+        let path = write("rules.toml", r#"
+            [[rules]]
+            keywords = ["hours"]
+            reply = "9 to 5"
+            stop = true
+
+            [[rules]]
+            contains = "urgent"
+            direct = false
+            react = "🚨"
+            forward_to_group = "oncall"
+
+            [[rules]]
+            schedule = { days = ["fri"], from = "18:00", to = "09:00" }
+            reply = "away"
+            cooldown = 60
+        "#);
+        let mut rules = RulesEngine::load(&path).unwrap();
+
+        let friday_night = NaiveDate::from_ymd_opt(2021, 8, 6).unwrap().and_hms_opt(20, 0, 0).unwrap();
+        let saturday_morning = NaiveDate::from_ymd_opt(2021, 8, 7).unwrap().and_hms_opt(8, 0, 0).unwrap();
+        let saturday_noon = NaiveDate::from_ymd_opt(2021, 8, 7).unwrap().and_hms_opt(12, 0, 0).unwrap();
+
+        assert_eq!(summary(rules.evaluate_at(&message("Opening HOURS?", None), friday_night)), ["send +15550000001 9 to 5"]);
+        assert_eq!(
+            summary(rules.evaluate_at(&message("urgent: it's down", Some("team")), saturday_noon)),
+            ["react 🚨", "send oncall +15550000001: urgent: it's down"]
+        );
+        // Direct messages don't match `direct = false`
+        assert!(rules.evaluate_at(&message("urgent", None), saturday_noon).is_empty());
+
+        // The away window wraps into Saturday morning, and only replies once per cooldown
+        assert_eq!(summary(rules.evaluate_at(&message("hi", None), saturday_morning)), ["send +15550000001 away"]);
+        assert!(rules.evaluate_at(&message("hi again", None), saturday_morning).is_empty());
+        assert_eq!(summary(rules.evaluate_at(&message("hi", Some("team")), saturday_morning)), ["send team away"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn from_normalized_address() {
+        let path = write("from.toml", r#"
+            [[rules]]
+            from = ["+1 (555) 000-0001"]
+            reply = "hi"
+        "#);
+        let mut rules = RulesEngine::load(&path).unwrap();
+        assert_eq!(summary(rules.evaluate_at(&message("hello", None), NaiveDate::from_ymd_opt(2021, 8, 6).unwrap().and_hms_opt(12, 0, 0).unwrap())), ["send +15550000001 hi"]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn yaml_and_reload() {
+        let path = write("rules.yaml", "rules:\n  - keywords: [ping]\n    reply: pong\n");
+        let mut rules = RulesEngine::load(&path).unwrap();
+        assert_eq!(summary(rules.evaluate(&message("ping", None))), ["send +15550000001 pong"]);
+        assert!(!rules.reload_if_changed().unwrap());
+
+        std::fs::write(&path, "rules:\n  - keywords: [ping, table tennis]\n    react: 🏓\n").unwrap();
+        assert!(rules.reload_if_changed().unwrap());
+        assert_eq!(summary(rules.evaluate(&message("ping", None))), ["react 🏓"]);
+
+        // Edits that keep the file's length are still noticed
+        std::fs::write(&path, "rules:\n  - keywords: [pong, table tennis]\n    react: 🏓\n").unwrap();
+        assert!(rules.reload_if_changed().unwrap());
+        assert!(rules.evaluate(&message("ping", None)).is_empty());
+
+        // Invalid files keep the old rules, and are only reported once
+        std::fs::write(&path, "rules:\n  - keyword: [ping]\n").unwrap();
+        assert!(rules.reload_if_changed().is_err());
+        assert!(!rules.reload_if_changed().unwrap());
+        assert_eq!(rules.rules().len(), 1);
+
+        // Schedules are checked when the file is loaded, not when a message arrives
+        std::fs::write(&path, "rules:\n  - schedule: { days: [someday], from: '09:00', to: '17:00' }\n    reply: open\n").unwrap();
+        assert!(matches!(rules.reload_if_changed(), Err(RulesError::Parse(e)) if e.contains("invalid day someday")));
+        assert!(matches!(RulesEngine::load(&path), Err(RulesError::Parse(_))));
+        std::fs::write(&path, "rules:\n  - schedule: { from: '9am', to: '17:00' }\n    reply: open\n").unwrap();
+        assert!(RulesEngine::load(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
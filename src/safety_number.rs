@@ -0,0 +1,299 @@
+//! Verifying contacts' safety numbers without a phone.
+//!
+//! [`IdentityKeyV1`] carries a contact's safety number both as digits and as `qr_code_data`,
+//! the base64 encoded payload of the QR code Signal shows under "View safety number". The
+//! helpers here compare a safety number someone typed against the stored one, show the QR code
+//! for the contact to scan, and check a payload scanned from the contact's phone locally. Once a
+//! contact is verified, pass the safety number or QR code data to `trust` with the
+//! `TRUSTED_VERIFIED` trust level.
+
+use std::fmt::Display;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::fingerprint::digits;
+use crate::qr::Qr;
+use crate::types::IdentityKeyV1;
+
+/// Digits in a safety number, 30 for each side of the conversation
+const DIGITS: usize = 60;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The key has no QR code data, or it isn't a valid fingerprint
+    InvalidPayload,
+    /// The scanned code was made by an incompatible version of Signal
+    VersionMismatch,
+    /// The scanned code belongs to different keys
+    Mismatch
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::InvalidPayload => write!(f, "Invalid safety number QR code"),
+            VerifyError::VersionMismatch => write!(f, "Safety number QR code is from a different version of Signal"),
+            VerifyError::Mismatch => write!(f, "Safety numbers don't match")
+        }
+    }
+}
+
+/// The digits of a safety number, ignoring spaces and dashes. Returns `None` if it isn't 60
+/// digits long.
+pub fn normalize(safety_number: &str) -> Option<String> {
+    let digits: String = safety_number.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect();
+
+    match digits.len() == DIGITS && digits.chars().all(|c| c.is_ascii_digit()) {
+        true => Some(digits),
+        false => None
+    }
+}
+
+/// Whether two safety numbers are the same, however they are spaced
+pub fn matches(typed: &str, stored: &str) -> bool {
+    match (normalize(typed), normalize(stored)) {
+        (Some(typed), Some(stored)) => typed == stored,
+        _ => false
+    }
+}
+
+/// Split a safety number into the twelve groups of five digits Signal shows
+pub fn format(safety_number: &str) -> Option<String> {
+    let digits = normalize(safety_number)?;
+    let groups: Vec<_> = digits.as_bytes()
+        .chunks(5)
+        .map(|group| std::str::from_utf8(group).unwrap())
+        .collect();
+
+    Some(groups.join(" "))
+}
+
+/// The decoded payload of a safety number QR code
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub version: u32,
+    /// Fingerprint of the key of whoever shows the code
+    pub local: Vec<u8>,
+    /// Fingerprint of the key of who they are talking to
+    pub remote: Vec<u8>
+}
+
+impl Fingerprint {
+    /// Decode a scanned QR code payload, a `CombinedFingerprints` protobuf message
+    pub fn decode(payload: &[u8]) -> Result<Self, VerifyError> {
+        let mut version = None;
+        let mut local = None;
+        let mut remote = None;
+
+        for field in Fields(payload) {
+            match field? {
+                (1, Field::Varint(value)) => version = Some(value as u32),
+                (2, Field::Bytes(message)) => local = Some(content(message)?),
+                (3, Field::Bytes(message)) => remote = Some(content(message)?),
+                _ => {}
+            }
+        }
+
+        match (version, local, remote) {
+            (Some(version), Some(local), Some(remote)) => Ok(Fingerprint { version, local, remote }),
+            _ => Err(VerifyError::InvalidPayload)
+        }
+    }
+
+    /// Decode a base64 encoded payload, like `qr_code_data`
+    pub fn from_base64(payload: &str) -> Result<Self, VerifyError> {
+        let payload = STANDARD.decode(payload.trim()).map_err(|_| VerifyError::InvalidPayload)?;
+        Self::decode(&payload)
+    }
+
+    /// The safety number the fingerprints display as
+    pub fn safety_number(&self) -> Result<String, VerifyError> {
+        let local = digits(&self.local).ok_or(VerifyError::InvalidPayload)?;
+        let remote = digits(&self.remote).ok_or(VerifyError::InvalidPayload)?;
+
+        // Both sides show the same number, so the lower half always comes first
+        match local <= remote {
+            true => Ok(local + &remote),
+            false => Ok(remote + &local)
+        }
+    }
+
+    /// Check a code scanned from the contact's phone against ours. Their local fingerprint is
+    /// our remote one, and the other way around.
+    pub fn verify(&self, scanned: &Fingerprint) -> Result<(), VerifyError> {
+        if self.version != scanned.version {
+            return Err(VerifyError::VersionMismatch);
+        }
+
+        match self.local == scanned.remote && self.remote == scanned.local {
+            true => Ok(()),
+            false => Err(VerifyError::Mismatch)
+        }
+    }
+}
+
+impl IdentityKeyV1 {
+    /// Decode the key's QR code data
+    pub fn fingerprint(&self) -> Result<Fingerprint, VerifyError> {
+        Fingerprint::from_base64(self.qr_code_data.as_deref().ok_or(VerifyError::InvalidPayload)?)
+    }
+
+    /// Whether `typed` is this key's safety number, however it is spaced
+    pub fn matches_safety_number(&self, typed: &str) -> bool {
+        self.safety_number.as_deref().is_some_and(|stored| matches(typed, stored))
+    }
+
+    /// Check a payload scanned from the contact's phone against this key
+    pub fn verify_scanned(&self, payload: &[u8]) -> Result<(), VerifyError> {
+        self.fingerprint()?.verify(&Fingerprint::decode(payload)?)
+    }
+
+    /// The QR code for the contact to scan, to render with [`Qr::to_terminal_string`] or
+    /// [`Qr::save_png`]
+    pub fn qr_code(&self) -> Result<Qr, VerifyError> {
+        let data = self.qr_code_data.as_deref().ok_or(VerifyError::InvalidPayload)?;
+        let payload = STANDARD.decode(data.trim()).map_err(|_| VerifyError::InvalidPayload)?;
+        Qr::new(payload).ok_or(VerifyError::InvalidPayload)
+    }
+}
+
+/// The `content` of a `LogicalFingerprint` message
+fn content(message: &[u8]) -> Result<Vec<u8>, VerifyError> {
+    for field in Fields(message) {
+        if let (1, Field::Bytes(content)) = field? {
+            return Ok(content.to_vec());
+        }
+    }
+
+    Err(VerifyError::InvalidPayload)
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed
+}
+
+/// The fields of a protobuf message, which is all the fingerprint format needs
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn varint(&mut self) -> Result<u64, VerifyError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = self.0.split_first().ok_or(VerifyError::InvalidPayload)?;
+            self.0 = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(VerifyError::InvalidPayload)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], VerifyError> {
+        if self.0.len() < len {
+            return Err(VerifyError::InvalidPayload);
+        }
+
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn field(&mut self) -> Result<(u64, Field<'a>), VerifyError> {
+        let key = self.varint()?;
+        let field = match key & 7 {
+            0 => Field::Varint(self.varint()?),
+            1 => { self.take(8)?; Field::Fixed },
+            2 => {
+                let len = self.varint()? as usize;
+                Field::Bytes(self.take(len)?)
+            },
+            5 => { self.take(4)?; Field::Fixed },
+            _ => { return Err(VerifyError::InvalidPayload); }
+        };
+
+        Ok((key >> 3, field))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Field<'a>), VerifyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let field = self.field();
+        if field.is_err() {
+            // Stop after the first error rather than reading garbage
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use super::{format, matches, Fingerprint, VerifyError};
+    use crate::types::IdentityKeyV1;
+
+    /// Encode fingerprints the way Signal does, as `CombinedFingerprints`
+    fn encode(version: u8, local: &[u8], remote: &[u8]) -> Vec<u8> {
+        let mut payload = vec![0x08, version];
+        for (tag, content) in [(0x12, local), (0x1a, remote)] {
+            payload.extend([tag, content.len() as u8 + 2, 0x0a, content.len() as u8]);
+            payload.extend(content);
+        }
+        payload
+    }
+
+    fn identity(payload: &[u8]) -> IdentityKeyV1 {
+        let fingerprint = Fingerprint::decode(payload).unwrap();
+        IdentityKeyV1 {
+            qr_code_data: Some(STANDARD.encode(payload)),
+            safety_number: Some(fingerprint.safety_number().unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn typed_safety_numbers() {
+        let stored = "373453558586758076680580548714989751943247272727416091564451";
+        assert!(matches("37345 35585 86758 07668 05805 48714 98975 19432 47272 72741 60915 64451", stored));
+        assert!(matches(" 37345-35585-86758-07668\n05805-48714-98975-19432\n47272-72741-60915-64451 ", stored));
+        assert!(!matches("37345 35585", stored));
+        assert!(!matches(&stored.replace('3', "4"), stored));
+        assert_eq!(format(stored).unwrap().split(' ').count(), 12);
+    }
+
+    #[test]
+    fn scanned_codes() {
+        let ours = [1u8; 32];
+        let theirs = [2u8; 32];
+        let identity = identity(&encode(2, &ours, &theirs));
+
+        // 0x0101010101 % 100000 and 0x0202020202 % 100000, six times each
+        let safety_number = identity.safety_number.clone().unwrap();
+        assert_eq!(&safety_number[..5], "10305");
+        assert_eq!(&safety_number[30..35], "20610");
+        // Both sides compute the same number
+        assert_eq!(Fingerprint::decode(&encode(2, &theirs, &ours)).unwrap().safety_number().unwrap(), safety_number);
+        assert!(identity.matches_safety_number(&format(&safety_number).unwrap()));
+
+        assert_eq!(identity.verify_scanned(&encode(2, &theirs, &ours)), Ok(()));
+        assert_eq!(identity.verify_scanned(&encode(2, &[3; 32], &ours)), Err(VerifyError::Mismatch));
+        assert_eq!(identity.verify_scanned(&encode(1, &theirs, &ours)), Err(VerifyError::VersionMismatch));
+        assert_eq!(identity.verify_scanned(&[0x12, 0x40]), Err(VerifyError::InvalidPayload));
+
+        let qr = identity.qr_code().unwrap();
+        assert!(qr.to_terminal_string().lines().count() > 10);
+    }
+}
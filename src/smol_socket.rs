@@ -0,0 +1,203 @@
+use smol::net::unix::UnixStream;
+use smol::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Error};
+use smol::channel::{bounded, Receiver, RecvError, Sender};
+use smol::Task;
+use std::sync::Arc;
+use std::path::Path;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+use async_trait::async_trait;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::errors::SignaldError;
+use crate::socket::{AsyncSocket, PendingRequest};
+use crate::actions::SocketWrapper;
+use crate::types::IncomingMessageV1;
+
+pub enum SocketError {
+    General(&'static str),
+    Io(Error),
+    Channel(RecvError),
+    Signald(SignaldError)
+}
+
+impl Display for SocketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketError::General(desc) => write!(f, "Error: {}", desc),
+            SocketError::Io(e) => write!(f, "Error: {}", e),
+            SocketError::Channel(e) => write!(f, "Error: {}", e),
+            SocketError::Signald(e) => write!(f, "Signald error: {}", e.error.message)
+        }
+    }
+}
+
+impl Debug for SocketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocketError::General(desc) => write!(f, "Error: {}", desc),
+            SocketError::Io(e) => write!(f, "Error: {}", e),
+            SocketError::Channel(e) => write!(f, "Error: {}", e),
+            SocketError::Signald(e) => write!(f, "Signald error: {}", e.error.message)
+        }
+    }
+}
+
+impl From<Error> for SocketError {
+    fn from(e: Error) -> Self {
+        SocketError::Io(e)
+    }
+}
+
+impl From<RecvError> for SocketError {
+    fn from(e: RecvError) -> Self {
+        SocketError::Channel(e)
+    }
+}
+
+pub type Map = Arc<std::sync::Mutex<HashMap<Uuid, (Sender<Value>, Receiver<Value>)>>>;
+
+pub struct Socket<T> {
+    socket: T,
+    pub(crate) response_map: Map,
+    /// Holds its own handle to the stream. Dropping the task cancels it, which closes the
+    /// connection.
+    _listener: Task<()>
+}
+
+#[async_trait]
+impl AsyncSocket for Socket<UnixStream> {
+    async fn write<'a>(&'a mut self, buf: &'a [u8], id: &Uuid) -> Result<(), SocketError> {
+        let channel = bounded(1);
+        self.response_map.lock().unwrap().insert(
+            *id,
+            channel
+        );
+        let pending = PendingRequest::new(&self.response_map, *id);
+
+        match self.socket.write_all(buf).await {
+            Ok(()) => {
+                pending.keep();
+                Ok(())
+            },
+            Err(e) => Err(SocketError::Io(e))
+        }
+    }
+
+    async fn get_response<'a>(&'a mut self, id: Uuid) -> Result<Value, SocketError> {
+        let receiver = match self.response_map.lock().unwrap().get(&id) {
+            Some(channel) => channel.1.clone(),
+            None => { return Err(SocketError::General("Error: Incorrect response ID")); }
+        };
+        let _pending = PendingRequest::new(&self.response_map, id);
+
+        receiver.recv().await.map_err(SocketError::Channel)
+    }
+}
+
+impl Socket<UnixStream> {
+    pub async fn connect<P, F>(path: P, handler: F) -> Result<Self, SocketError>
+    where P: AsRef<Path>, F: Fn(IncomingMessageV1) + 'static + Send,
+    {
+        let socket = UnixStream::connect(path).await?;
+        let response_map = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        let listener = smol::spawn(listen(
+            socket.clone(),
+            response_map.clone(),
+            handler
+        ));
+
+        crate::metrics::connected();
+        Ok(Socket {
+            socket,
+            response_map,
+            _listener: listener
+        })
+    }
+}
+
+async fn listen<F>(socket: UnixStream, map: Map, handler: F)
+where F: Fn(IncomingMessageV1) + 'static + Send,
+{
+    let mut reader = BufReader::new(socket);
+    let mut buf = String::with_capacity(1024);
+
+    loop {
+        match reader.read_line(&mut buf).await {
+            Ok(0) => break,
+            Ok(_) => {
+                match serde_json::from_str::<Value>(buf.as_str()) {
+                    Ok(mut response) => {
+                        if let Some(id) = response.get("id") {
+                            match id.as_str().and_then(|id| Uuid::parse_str(id).ok()) {
+                                Some(id) => respond(&map, id, response),
+                                None => warn!("Response packet has an invalid id"),
+                            }
+                        } else {
+                            debug!("Received {} message", response.get("type").and_then(|msg| msg.as_str()).unwrap_or_default());
+                            if let Some(msg) = response.get_mut("data") {
+                                let msg = serde_json::from_value::<IncomingMessageV1>(
+                                    msg.take()
+                                );
+
+                                if let Ok(msg) = msg {
+                                    crate::metrics::message_received(msg.account.as_deref());
+                                    (handler)(msg);
+                                }
+                            }
+                        }
+                    },
+                    Err(e) => warn!("Failed to parse packet: {}", e)
+                }
+            },
+            Err(e) => {
+                error!("Failed to read from socket: {}", e);
+            }
+        }
+
+        buf.clear();
+    }
+
+    // Nothing will answer the requests still waiting, so close their channels to fail them
+    // instead of leaving them hanging. Responses that already arrived can still be received.
+    for (sender, _) in map.lock().unwrap().values() {
+        sender.close();
+    }
+}
+
+/// Hand a response to the request waiting on it. Responses to requests that were cancelled, or
+/// that never existed, are dropped.
+fn respond(map: &Map, id: Uuid, mut response: Value) {
+    let sender = match map.lock().unwrap().get(&id) {
+        Some(channel) => channel.0.clone(),
+        None => {
+            debug!("Dropping response to unknown request {}", id);
+            return;
+        }
+    };
+
+    // Errors are reported on the packet itself rather than in the data field
+    let response = match response.get("error") {
+        Some(_) => response,
+        None => response.get_mut("data").map(Value::take).unwrap_or_default()
+    };
+
+    if let Err(e) = sender.try_send(response) {
+        warn!("Failed to deliver response to request {}: {}", id, e);
+    }
+}
+
+pub(crate) async fn sleep(duration: std::time::Duration) {
+    smol::Timer::after(duration).await;
+}
+
+pub type Signald = SocketWrapper<Socket<UnixStream>>;
+
+impl Signald {
+    pub async fn connect<P, F>(path: P, handler: F) -> Result<Self, SocketError>
+    where P: AsRef<Path>, F: Fn(IncomingMessageV1) + 'static + Send,
+    {
+        Ok(Signald::new(Socket::connect(path, handler).await?))
+    }
+}
@@ -22,11 +22,7 @@ pub struct MessageCommon<T> {
 }
 
 fn is_empty<T>(_data: &T) -> bool {
-    if std::mem::size_of::<T>() > 0 {
-        false
-    } else {
-        true
-    }
+    std::mem::size_of::<T>() == 0
 }
 
 #[derive(Serialize, Default)]
@@ -46,3 +42,143 @@ impl<T> MessageCommon<T> {
         }
     }
 }
+
+/// Removes a request from a socket's response map when dropped, so a request future that is
+/// cancelled part way through doesn't leave its channel registered forever.
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+pub(crate) struct PendingRequest<'a, V> {
+    map: &'a std::sync::Mutex<std::collections::HashMap<Uuid, V>>,
+    id: Uuid,
+    armed: bool
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+impl<'a, V> PendingRequest<'a, V> {
+    pub(crate) fn new(map: &'a std::sync::Mutex<std::collections::HashMap<Uuid, V>>, id: Uuid) -> Self {
+        PendingRequest {
+            map,
+            id,
+            armed: true
+        }
+    }
+
+    /// Keep the request registered once the guard is dropped
+    pub(crate) fn keep(mut self) {
+        self.armed = false;
+    }
+}
+
+#[cfg(any(feature = "async-std", feature = "tokio", feature = "smol"))]
+impl<V> Drop for PendingRequest<'_, V> {
+    fn drop(&mut self) {
+        if self.armed {
+            if let Ok(mut map) = self.map.lock() {
+                map.remove(&self.id);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "async-std", feature = "tokio", feature = "smol")))]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use serde_json::Value;
+    use crate::test_support::{block_on, connect, timeout};
+    use crate::types::VersionRequestV1;
+    use crate::SocketError;
+
+    /// Serve a single connection at a fresh socket path on another thread
+    fn serve<F>(name: &str, server: F) -> (PathBuf, std::thread::JoinHandle<()>)
+    where F: FnOnce(UnixStream) + Send + 'static,
+    {
+        let path = std::env::temp_dir().join(format!("signald-{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = std::thread::spawn(move || server(listener.accept().unwrap().0));
+        (path, server)
+    }
+
+    fn request(lines: &mut impl BufRead) -> Value {
+        let mut line = String::new();
+        lines.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn cancelled_request() -> Result<(), SocketError> {
+        let (path, server) = serve("cancel", |mut stream| {
+            let mut lines = BufReader::new(stream.try_clone().unwrap());
+            let late = request(&mut lines);
+            let request = request(&mut lines);
+
+            for id in [&late["id"], &request["id"]] {
+                let response = format!("{{\"id\":{},\"type\":\"version\",\"data\":{{\"name\":\"signald\"}}}}\n", id);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        block_on(async {
+            let mut socket = connect(&path).await?;
+
+            let cancelled = timeout(Duration::from_millis(50), socket.version(VersionRequestV1, None)).await;
+            assert!(cancelled.is_none());
+            assert!(socket.socket.response_map.lock().unwrap().is_empty());
+
+            let version = socket.version(VersionRequestV1, None).await?;
+            assert_eq!(version.name.as_deref(), Some("signald"));
+            assert!(socket.socket.response_map.lock().unwrap().is_empty());
+            Ok::<_, SocketError>(())
+        })?;
+
+        server.join().unwrap();
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn closed_connection_fails_pending_requests() -> Result<(), SocketError> {
+        let (path, server) = serve("closed", |stream| {
+            // Read the request, then hang up without answering
+            request(&mut BufReader::new(stream));
+        });
+
+        block_on(async {
+            let mut socket = connect(&path).await?;
+
+            let response = timeout(Duration::from_secs(5), socket.version(VersionRequestV1, None)).await
+                .expect("pending requests should fail once the connection closes");
+            assert!(matches!(response, Err(SocketError::Channel(_))));
+            assert!(socket.socket.response_map.lock().unwrap().is_empty());
+            Ok::<_, SocketError>(())
+        })?;
+
+        server.join().unwrap();
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn drop_closes_connection() -> Result<(), SocketError> {
+        let (path, server) = serve("drop", |mut stream| {
+            // Only returns once every handle to the client's end is closed
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            stream.read_to_end(&mut Vec::new()).expect("dropping the socket should close the connection");
+        });
+
+        block_on(async {
+            let socket = connect(&path).await?;
+            drop(socket);
+            // Give the runtime a chance to stop the listener
+            crate::sleep(Duration::from_millis(50)).await;
+            Ok::<_, SocketError>(())
+        })?;
+
+        server.join().unwrap();
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
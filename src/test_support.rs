@@ -0,0 +1,131 @@
+//! Fixtures shared by the unit tests.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::Path;
+use std::task::Poll;
+use std::time::Duration;
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::socket::AsyncSocket;
+use crate::{Signald, SocketError};
+
+/// Records every request and replies to each with the next canned response. Once those run
+/// out it fails, or replies `{}` if it was made with [`ScriptedSocket::recording`].
+#[derive(Default)]
+pub(crate) struct ScriptedSocket {
+    pub sent: Vec<Value>,
+    pub responses: VecDeque<Value>,
+    fallback: Option<Value>
+}
+
+impl ScriptedSocket {
+    pub fn new(responses: Vec<Value>) -> Self {
+        ScriptedSocket {
+            responses: responses.into(),
+            ..Default::default()
+        }
+    }
+
+    /// A socket that replies `{}` to every request
+    pub fn recording() -> Self {
+        ScriptedSocket {
+            fallback: Some(json!({})),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncSocket for ScriptedSocket {
+    async fn write<'a>(&'a mut self, buf: &'a [u8], _id: &Uuid) -> Result<(), SocketError> {
+        self.sent.push(serde_json::from_slice(buf).unwrap());
+        Ok(())
+    }
+
+    async fn get_response<'a>(&'a mut self, _id: Uuid) -> Result<Value, SocketError> {
+        self.responses.pop_front()
+            .or_else(|| self.fallback.clone())
+            .ok_or(SocketError::General("No response"))
+    }
+}
+
+/// Run a test future on the runtime the crate was built with
+#[cfg(feature = "async-std")]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    async_std::task::block_on(future)
+}
+
+#[cfg(feature = "smol")]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    smol::block_on(future)
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// Run `future` in the background, returning a future of its output. Under smol the task is
+/// cancelled if that future is dropped, so keep it until the test is done.
+#[cfg(feature = "async-std")]
+pub(crate) fn spawn<F>(future: F) -> impl Future<Output = F::Output>
+where F: Future + Send + 'static, F::Output: Send + 'static,
+{
+    async_std::task::spawn(future)
+}
+
+#[cfg(feature = "smol")]
+pub(crate) fn spawn<F>(future: F) -> impl Future<Output = F::Output>
+where F: Future + Send + 'static, F::Output: Send + 'static,
+{
+    smol::spawn(future)
+}
+
+#[cfg(feature = "tokio")]
+pub(crate) fn spawn<F>(future: F) -> impl Future<Output = F::Output>
+where F: Future + Send + 'static, F::Output: Send + 'static,
+{
+    let task = tokio::task::spawn(future);
+    async move { task.await.unwrap() }
+}
+
+/// The output of `future`, or `None` if it takes longer than `duration`
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    let mut future = std::pin::pin!(future);
+    let mut sleep = std::pin::pin!(crate::sleep(duration));
+
+    std::future::poll_fn(|cx| match future.as_mut().poll(cx) {
+        Poll::Ready(output) => Poll::Ready(Some(output)),
+        Poll::Pending => sleep.as_mut().poll(cx).map(|_| None)
+    }).await
+}
+
+/// Let other tasks run before continuing
+#[cfg(any(feature = "async-std", feature = "smol"))]
+pub(crate) async fn yield_now() {
+    let mut yielded = false;
+
+    std::future::poll_fn(|cx| match yielded {
+        true => Poll::Ready(()),
+        false => {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }).await
+}
+
+/// Connect to `path` whichever backend the crate was built with, ignoring incoming messages
+pub(crate) async fn connect(path: &Path) -> Result<Signald, SocketError> {
+    #[cfg(feature = "tokio")]
+    return Signald::connect(path).await;
+    #[cfg(not(feature = "tokio"))]
+    return Signald::connect(path, |_| {}).await;
+}
@@ -4,6 +4,7 @@ use tokio::sync::mpsc::{Sender, Receiver};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
 use tokio::sync::mpsc;
 use tokio::io::BufReader;
+use tokio::task::JoinHandle;
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
 use uuid::Uuid;
@@ -14,7 +15,7 @@ use std::io::Error;
 use std::path::Path;
 
 use crate::errors::SignaldError;
-use crate::socket::AsyncSocket;
+use crate::socket::{AsyncSocket, PendingRequest};
 use crate::actions::SocketWrapper;
 
 pub enum SocketError {
@@ -45,8 +46,9 @@ pub type Map = Arc<Mutex<HashMap<Uuid, (Sender<Value>, Option<Receiver<Value>>)>
 
 pub struct Socket<T> {
     socket: T,
-    response_map: Map,
-    listening: Arc<Mutex<bool>>
+    pub(crate) response_map: Map,
+    /// Aborted on drop, which closes the read half of the stream
+    listener: JoinHandle<()>
 }
 
 #[async_trait]
@@ -57,16 +59,24 @@ impl AsyncSocket for Socket<OwnedWriteHalf> {
             *id,
             (channel.0, Some(channel.1))
         );
+        let pending = PendingRequest::new(&self.response_map, *id);
 
         self.socket.write_all(buf).await?;
+        pending.keep();
         Ok(())
     }
 
     async fn get_response<'a>(&'a mut self, id: Uuid) -> Result<Value, SocketError> {
-        let mut receiver = match self.response_map.lock().unwrap().get_mut(&id) {
-            Some(channel) => channel.1.take().unwrap(),
+        let receiver = match self.response_map.lock().unwrap().get_mut(&id) {
+            Some(channel) => channel.1.take(),
             None => { return Err(SocketError::General("Error: Incorrect response ID")); }
         };
+        let _pending = PendingRequest::new(&self.response_map, id);
+
+        let mut receiver = match receiver {
+            Some(receiver) => receiver,
+            None => { return Err(SocketError::General("Error: Response already awaited")); }
+        };
 
         receiver.recv().await.ok_or(SocketError::Channel("Failed to receive response"))
     }
@@ -76,58 +86,96 @@ impl Socket<OwnedWriteHalf> {
     pub async fn connect<P: AsRef<Path>>(path: P) -> Result<Self, SocketError> {
         let (reader, writer) = UnixStream::connect(path).await?.into_split();
         let response_map = Arc::new(Mutex::new(HashMap::new()));
-        let listening = Arc::new(Mutex::new(true));
+        let listener = tokio::task::spawn(listen(
+            reader,
+            response_map.clone()
+        ));
 
-        let socket_wrapper = Socket {
+        crate::metrics::connected();
+        Ok(Socket {
             socket: writer,
-            response_map: response_map.clone(),
-            listening: listening.clone()
-        };
-
-        tokio::task::spawn(async move {
-            listen(
-                reader,
-                response_map,
-                listening
-            ).await;
-        });
-
-        Ok(socket_wrapper)
+            response_map,
+            listener
+        })
     }
 }
 
-async fn listen(socket: OwnedReadHalf, map: Map, listening: Arc<Mutex<bool>>) {
+async fn listen(socket: OwnedReadHalf, map: Map) {
     let mut reader = BufReader::new(socket);
     let mut buf = String::with_capacity(1024);
 
-    while *listening.lock().unwrap() {
+    loop {
         match reader.read_line(&mut buf).await {
+            Ok(0) => break,
             Ok(_) => {
-                let response: Value = serde_json::from_str(buf.as_str()).unwrap();
-                if let Some(id) = response.get("id") {
-                    let id = Uuid::parse_str(id.as_str().unwrap()).unwrap();
-                    let sender = map.lock().unwrap().get(&id).unwrap().0.clone();
-                    match sender.send(response).await {
-                        Ok(_) => {},
-                        Err(e) => println!("Error sending response: {}", e)
-                    }
+                match serde_json::from_str::<Value>(buf.as_str()) {
+                    Ok(response) => {
+                        if let Some(id) = response.get("id") {
+                            match id.as_str().and_then(|id| Uuid::parse_str(id).ok()) {
+                                Some(id) => respond(&map, id, response),
+                                None => warn!("Response packet has an invalid id"),
+                            }
+                        }
+                    },
+                    Err(e) => warn!("Failed to parse packet: {}", e)
                 }
             },
             Err(e) => {
-                println!("Error: {}", e.to_string());
+                error!("Failed to read from socket: {}", e);
             }
         }
 
         buf.clear();
     }
+
+    // Nothing will answer the requests still waiting, so fail them instead of leaving them hanging.
+    // Dropping the sender fails a request that is already awaited, and closing the receiver of one
+    // that isn't yet still lets it receive a response that already arrived.
+    map.lock().unwrap().retain(|_, (_, receiver)| match receiver {
+        Some(receiver) => {
+            receiver.close();
+            true
+        },
+        None => false
+    });
+}
+
+/// Hand a response to the request waiting on it. Responses to requests that were cancelled, or
+/// that never existed, are dropped.
+fn respond(map: &Map, id: Uuid, mut response: Value) {
+    let sender = match map.lock().unwrap().get(&id) {
+        Some(channel) => channel.0.clone(),
+        None => {
+            debug!("Dropping response to unknown request {}", id);
+            return;
+        }
+    };
+
+    // Errors are reported on the packet itself rather than in the data field
+    let response = match response.get("error") {
+        Some(_) => response,
+        None => response.get_mut("data").map(Value::take).unwrap_or_default()
+    };
+
+    if let Err(e) = sender.try_send(response) {
+        warn!("Failed to deliver response to request {}: {}", id, e);
+    }
+}
+
+pub(crate) async fn sleep(duration: std::time::Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+impl<T> Drop for Socket<T> {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
 }
 
 pub type Signald = SocketWrapper<Socket<OwnedWriteHalf>>;
 
 impl Signald {
     pub async fn connect<P: AsRef<Path>>(path: P) -> Result<Self, SocketError> {
-        Ok(Signald {
-            socket: Socket::connect(path).await?,
-        })
+        Ok(Signald::new(Socket::connect(path).await?))
     }
 }
@@ -0,0 +1,121 @@
+//! Internal instrumentation. Events and request spans are emitted through `tracing` when the
+//! `tracing` feature is enabled, and compiled out otherwise. Request outcomes are also reported
+//! to the installed [`MetricsHook`](crate::metrics::MetricsHook), if any.
+
+// Which of the macros get used depends on the enabled runtime features
+#![allow(unused_macros)]
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::time::Instant;
+use serde_json::Value;
+use uuid::Uuid;
+
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($arg:tt)*) => { tracing::debug!($($arg)*) }
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! warn {
+    ($($arg:tt)*) => { tracing::warn!($($arg)*) }
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! error {
+    ($($arg:tt)*) => { tracing::error!($($arg)*) }
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($arg:tt)*) => { if false { let _ = format!($($arg)*); } }
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! warn {
+    ($($arg:tt)*) => { if false { let _ = format!($($arg)*); } }
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! error {
+    ($($arg:tt)*) => { if false { let _ = format!($($arg)*); } }
+}
+
+/// Run a request inside a `signald_request` span carrying its id and action, then report how
+/// long it took and whether signald returned an error
+pub(crate) async fn request<F, E>(id: &Uuid, action: &'static str, request: F) -> Result<Value, E>
+where F: Future<Output = Result<Value, E>>, E: Debug,
+{
+    let span = RequestSpan::new(id, action);
+
+    #[cfg(feature = "tracing")]
+    let result = tracing::Instrument::instrument(request, span.span.clone()).await;
+    #[cfg(not(feature = "tracing"))]
+    let result = request.await;
+
+    span.finish(&result);
+    result
+}
+
+/// Blocking version of [`request`]
+#[cfg(feature = "blocking")]
+pub(crate) fn request_blocking<F, E>(id: &Uuid, action: &'static str, request: F) -> Result<Value, E>
+where F: FnOnce() -> Result<Value, E>, E: Debug,
+{
+    let span = RequestSpan::new(id, action);
+
+    #[cfg(feature = "tracing")]
+    let result = span.span.in_scope(request);
+    #[cfg(not(feature = "tracing"))]
+    let result = request();
+
+    span.finish(&result);
+    result
+}
+
+struct RequestSpan {
+    action: &'static str,
+    start: Instant,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span
+}
+
+impl RequestSpan {
+    fn new(_id: &Uuid, action: &'static str) -> Self {
+        RequestSpan {
+            action,
+            start: Instant::now(),
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!(
+                "signald_request",
+                id = %_id.to_simple(),
+                action,
+                duration_ms = tracing::field::Empty
+            )
+        }
+    }
+
+    fn finish<E: Debug>(&self, result: &Result<Value, E>) {
+        let duration = self.start.elapsed();
+        let error_type = match result {
+            Ok(response) => response.get("error").map(|_| {
+                response.get("error_type").and_then(Value::as_str).unwrap_or("Unknown")
+            }),
+            Err(_) => Some("SocketError")
+        };
+
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("duration_ms", duration.as_millis() as u64);
+            self.span.in_scope(|| match (result, error_type) {
+                (Err(e), _) => tracing::warn!("request failed: {:?}", e),
+                (Ok(_), Some(error_type)) => tracing::debug!("signald returned {}", error_type),
+                (Ok(_), None) => tracing::debug!("request completed")
+            });
+        }
+
+        if let Some(hook) = crate::metrics::hook() {
+            hook.request_completed(self.action, duration, error_type);
+        }
+    }
+}
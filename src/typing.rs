@@ -0,0 +1,277 @@
+//! Showing a typing indicator while a reply is prepared.
+//!
+//! Signal clients hide a typing indicator if it isn't refreshed for a while, so long operations
+//! have to keep re-sending it. A [`Typing`] guard starts the indicator for a conversation, keeps
+//! it alive while running a future with [`Typing::during`], and stops it when the reply is sent,
+//! the guard is finished, or the guard is dropped. The guard holds its own handle to a client
+//! shared behind a mutex, so dropping it can still send the stop request in the background.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "async-std")]
+use async_std::sync::Mutex;
+#[cfg(feature = "tokio")]
+use tokio::sync::Mutex;
+#[cfg(feature = "smol")]
+use smol::lock::Mutex;
+
+use crate::actions::SocketWrapper;
+use crate::conversation::{Conversation, ConversationRequest};
+use crate::socket::AsyncSocket;
+use crate::types::{SendRequestV1, SendResponseV1, TypingRequestV1};
+use crate::SocketError;
+use crate::util::now;
+
+/// How often the indicator is re-sent. Signal clients hide it after 15 seconds.
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Guard returned by [`Typing::start`].
+///
+/// End it with [`send`](Self::send) or [`stop`](Self::stop). If it is dropped instead, the stop
+/// request is sent from a background task, and a failure is only logged.
+pub struct Typing<T>
+where T: AsyncSocket + Send + 'static,
+{
+    client: Arc<Mutex<SocketWrapper<T>>>,
+    request: TypingRequestV1,
+    refresh_interval: Duration,
+    last_sent: Instant,
+    /// Whether the indicator was already stopped, so dropping the guard has nothing left to do
+    stopped: bool
+}
+
+impl<T> Typing<T>
+where T: AsyncSocket + Send + 'static,
+{
+    /// Start showing `account` as typing in `conversation`
+    pub async fn start(client: &Arc<Mutex<SocketWrapper<T>>>, account: &str, conversation: &Conversation) -> Result<Self, SocketError> {
+        let mut typing = Typing {
+            client: client.clone(),
+            request: TypingRequestV1 {
+                account: Some(account.to_owned()),
+                ..Default::default()
+            }.to(conversation),
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            last_sent: Instant::now(),
+            stopped: false
+        };
+
+        if let Err(e) = typing.send_typing(true).await {
+            // Nothing was started, so there is nothing to stop
+            typing.stopped = true;
+            return Err(e);
+        }
+        Ok(typing)
+    }
+
+    pub fn refresh_interval(mut self, refresh_interval: Duration) -> Self {
+        self.refresh_interval = refresh_interval;
+        self
+    }
+
+    /// Re-send the indicator if the refresh interval has passed
+    pub async fn refresh(&mut self) -> Result<(), SocketError> {
+        if self.last_sent.elapsed() >= self.refresh_interval {
+            self.send_typing(true).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Run `future` to completion, refreshing the indicator while it runs. Failed refreshes are
+    /// logged rather than interrupting `future`.
+    pub async fn during<F: Future>(&mut self, future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+
+        loop {
+            let mut sleep = std::pin::pin!(crate::sleep(self.refresh_interval.saturating_sub(self.last_sent.elapsed())));
+            let output = std::future::poll_fn(|cx| match future.as_mut().poll(cx) {
+                Poll::Ready(output) => Poll::Ready(Some(output)),
+                Poll::Pending => sleep.as_mut().poll(cx).map(|()| None)
+            }).await;
+
+            match output {
+                Some(output) => { return output; },
+                None => if let Err(e) = self.refresh().await {
+                    warn!("Failed to refresh typing indicator: {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Send the reply, then stop the indicator. The result is the reply's even if stopping the
+    /// indicator fails, which is only logged.
+    pub async fn send(mut self, request: SendRequestV1) -> Result<SendResponseV1, SocketError> {
+        let response = self.client.lock().await.send(request, None).await;
+        if let Err(e) = self.stop_typing().await {
+            warn!("Failed to stop typing indicator: {:?}", e);
+        }
+        response
+    }
+
+    pub async fn stop(mut self) -> Result<(), SocketError> {
+        self.stop_typing().await
+    }
+
+    async fn stop_typing(&mut self) -> Result<(), SocketError> {
+        self.stopped = true;
+        self.send_typing(false).await
+    }
+
+    async fn send_typing(&mut self, typing: bool) -> Result<(), SocketError> {
+        let request = self.request(typing);
+        self.last_sent = Instant::now();
+        self.client.lock().await.typing(request, None).await
+    }
+
+    fn request(&self, typing: bool) -> TypingRequestV1 {
+        TypingRequestV1 {
+            typing: Some(typing),
+            when: Some(now() as i64),
+            ..self.request.clone()
+        }
+    }
+}
+
+impl<T> Drop for Typing<T>
+where T: AsyncSocket + Send + 'static,
+{
+    fn drop(&mut self) {
+        if self.stopped {
+            return;
+        }
+
+        let client = self.client.clone();
+        let request = self.request(false);
+        spawn(async move {
+            if let Err(e) = client.lock().await.typing(request, None).await {
+                warn!("Failed to stop typing indicator: {:?}", e);
+            }
+        });
+    }
+}
+
+#[cfg(feature = "async-std")]
+fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    async_std::task::spawn(future);
+}
+
+#[cfg(feature = "smol")]
+fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    smol::spawn(future).detach();
+}
+
+#[cfg(feature = "tokio")]
+fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => { runtime.spawn(future); },
+        Err(_) => warn!("Typing indicator dropped outside of a tokio runtime, it will time out")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+    use serde_json::json;
+    use super::{Mutex, Typing};
+    use crate::actions::SocketWrapper;
+    use crate::conversation::Conversation;
+    use crate::test_support::{block_on, timeout, ScriptedSocket};
+    use crate::types::SendRequestV1;
+    use crate::SocketError;
+
+    type Client = Arc<Mutex<SocketWrapper<ScriptedSocket>>>;
+
+    fn client(socket: ScriptedSocket) -> Client {
+        Arc::new(Mutex::new(SocketWrapper::new(socket)))
+    }
+
+    async fn actions(client: &Client) -> Vec<String> {
+        client.lock().await.socket.sent.iter()
+            .map(|sent| match sent["type"].as_str().unwrap() {
+                "typing" => format!("typing:{}", sent["typing"]),
+                action => action.to_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn refresh_and_send() -> Result<(), SocketError> {
+        block_on(async {
+            let client = client(ScriptedSocket::recording());
+            let conversation = Conversation::group("group");
+
+            let mut typing = Typing::start(&client, "+15550000000", &conversation).await?
+                .refresh_interval(Duration::from_millis(20));
+            let answer = typing.during(async {
+                crate::sleep(Duration::from_millis(50)).await;
+                42
+            }).await;
+            assert_eq!(answer, 42);
+            typing.send(SendRequestV1::default()).await?;
+
+            let actions = actions(&client).await;
+            assert!(actions.len() >= 4);
+            assert!(actions[..actions.len() - 2].iter().all(|action| action == "typing:true"));
+            assert_eq!(actions[actions.len() - 2..], ["send", "typing:false"]);
+            assert_eq!(client.lock().await.socket.sent[0]["group"], "group");
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn failed_refresh_keeps_running() -> Result<(), SocketError> {
+        block_on(async {
+            // Only the first request gets a response, so every refresh fails
+            let client = client(ScriptedSocket::new(vec![json!({})]));
+            let conversation = Conversation::group("group");
+
+            let mut typing = Typing::start(&client, "+15550000000", &conversation).await?
+                .refresh_interval(Duration::from_millis(10));
+            let answer = typing.during(async {
+                crate::sleep(Duration::from_millis(50)).await;
+                42
+            }).await;
+            assert_eq!(answer, 42);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn failed_stop_keeps_reply() -> Result<(), SocketError> {
+        block_on(async {
+            // The reply is sent, but stopping the indicator gets no response
+            let client = client(ScriptedSocket::new(vec![json!({}), json!({ "timestamp": 100 })]));
+            let conversation = Conversation::group("group");
+
+            let typing = Typing::start(&client, "+15550000000", &conversation).await?;
+            let response = typing.send(SendRequestV1::default()).await?;
+            assert_eq!(response.timestamp, Some(100));
+            assert_eq!(actions(&client).await, ["typing:true", "send", "typing:false"]);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn drop_stops_typing() -> Result<(), SocketError> {
+        block_on(async {
+            let client = client(ScriptedSocket::recording());
+            let conversation = Conversation::group("group");
+
+            drop(Typing::start(&client, "+15550000000", &conversation).await?);
+            let stopped = timeout(Duration::from_secs(1), async {
+                while actions(&client).await.len() < 2 {
+                    crate::sleep(Duration::from_millis(1)).await;
+                }
+            }).await;
+            assert!(stopped.is_some(), "dropping the guard should stop the indicator");
+            assert_eq!(actions(&client).await, ["typing:true", "typing:false"]);
+            assert_eq!(client.lock().await.socket.sent[1]["group"], "group");
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,33 @@
+//! Helpers shared by the modules built on the async clients.
+
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::SocketError;
+
+/// Milliseconds since the unix epoch, the unit signald uses for timestamps
+pub(crate) fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Read JSON written by [`save_json`], or `None` if the file doesn't exist yet. `error` is
+/// returned if it can't be parsed.
+pub(crate) fn load_json<V: DeserializeOwned>(path: &Path, error: &'static str) -> Result<Option<V>, SocketError> {
+    match fs::read(path) {
+        Ok(buf) => serde_json::from_slice(&buf).map(Some).map_err(|_| SocketError::General(error)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(SocketError::Io(e))
+    }
+}
+
+/// Write `value` as JSON to a temporary file and move it into place, so a crash never leaves a
+/// partially written file behind
+pub(crate) fn save_json<V: Serialize>(path: &Path, value: &V) -> Result<(), SocketError> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec(value).unwrap())?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}